@@ -0,0 +1,96 @@
+use discord::request::File;
+use discord::resource::Snowflake;
+use discord::user::User;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+// shown by /nes stats
+const TOP: usize = 5;
+
+struct Entry {
+    at: SystemTime,
+    user: Snowflake<User>,
+    action: String,
+}
+
+// who did what in a session, the counters outlive the entries they came from
+pub struct Activity {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+    presses: HashMap<Snowflake<User>, u64>,
+    started: Instant,
+    pub frames: u64,
+    pub resets: u64,
+}
+
+fn uptime(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    match minutes / 60 {
+        0 => format!("{minutes}m"),
+        hours => format!("{hours}h {:02}m", minutes % 60),
+    }
+}
+
+impl Activity {
+    pub fn new(capacity: usize) -> Activity {
+        Activity {
+            entries: VecDeque::new(),
+            capacity,
+            presses: HashMap::new(),
+            started: Instant::now(),
+            frames: 0,
+            resets: 0,
+        }
+    }
+
+    pub fn record(&mut self, user: Snowflake<User>, action: impl Into<String>) {
+        *self.presses.entry(user).or_default() += 1;
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry {
+            at: SystemTime::now(),
+            user,
+            action: action.into(),
+        });
+    }
+
+    pub fn summary(&self) -> String {
+        let mut top: Vec<_> = self.presses.iter().collect();
+        top.sort_by(|a, b| b.1.cmp(a.1));
+
+        let mut summary = String::from("**Top players**\n");
+        if top.is_empty() {
+            summary += "Nobody yet\n";
+        }
+        for (rank, (user, presses)) in top.iter().take(TOP).enumerate() {
+            summary += &format!("{}. <@{user}>: {presses} actions\n", rank + 1);
+        }
+        summary += &format!(
+            "\nFrames emulated: {}\nUptime: {}\nResets: {}",
+            self.frames,
+            uptime(self.started.elapsed()),
+            self.resets
+        );
+        summary
+    }
+
+    // the entries still in the log as csv
+    pub fn csv(&self) -> File {
+        let mut csv = String::from("timestamp,user,action\n");
+        for entry in &self.entries {
+            let at = entry.at.duration_since(UNIX_EPOCH).unwrap_or_default();
+            csv += &format!("{},{},{}\n", at.as_secs(), entry.user, entry.action);
+        }
+
+        File {
+            name: "activity.csv".into(),
+            typ: "text/csv".into(),
+            data: csv.into_bytes().into(),
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use crate::clock::EmuClock;
+use crate::error::Result;
+use crate::frame::{upscale, HEIGHT, WIDTH};
+use discord::request::File;
+
+// discord's default upload limit
+pub const ATTACHMENT_LIMIT: usize = 8 * 1024 * 1024;
+
+// never drop below 15 fps when shrinking an animation
+const MAX_STEP: u64 = 4;
+
+pub trait AnimEncoder {
+    // shown for `frames` emulated frames
+    fn push_frame(&mut self, rgba: &mut [u8], frames: u64) -> Result<()>;
+    fn finish(self) -> Result<()>;
+}
+
+#[derive(Clone, Copy)]
+pub enum AnimFormat {
+    Gif,
+    Apng,
+}
+
+impl AnimFormat {
+    // emulated frames per shown frame
+    fn step(self) -> u64 {
+        match self {
+            // the GIF encoder cannot keep up with the full frame rate
+            AnimFormat::Gif => 2,
+            AnimFormat::Apng => 1,
+        }
+    }
+}
+
+// frames are written as soon as they are pushed, only the encoded bytes are kept
+pub struct Gif<'a> {
+    clock: EmuClock,
+    scale: u32,
+    elapsed: u64,
+    encoder: gif::Encoder<&'a mut Vec<u8>>,
+}
+
+impl<'a> Gif<'a> {
+    pub fn new(bytes: &'a mut Vec<u8>, clock: EmuClock, scale: u32) -> Result<Gif<'a>> {
+        let (width, height) = ((WIDTH * scale) as u16, (HEIGHT * scale) as u16);
+        Ok(Gif {
+            clock,
+            scale,
+            elapsed: 0,
+            encoder: gif::Encoder::new(bytes, width, height, &[])?,
+        })
+    }
+}
+
+impl AnimEncoder for Gif<'_> {
+    fn push_frame(&mut self, rgba: &mut [u8], frames: u64) -> Result<()> {
+        let (width, height) = ((WIDTH * self.scale) as u16, (HEIGHT * self.scale) as u16);
+        // at 1x the raw frame is quantized in place, fully opaque pixels come out unchanged
+        let mut scaled;
+        let pixels = if self.scale > 1 {
+            scaled = upscale(rgba, self.scale).into_owned();
+            &mut scaled[..]
+        } else {
+            rgba
+        };
+        let mut frame = gif::Frame::from_rgba_speed(width, height, pixels, 30);
+
+        frame.delay = self.clock.gif_delay(self.elapsed, frames);
+        self.elapsed += frames;
+
+        self.encoder.write_frame(&frame)?;
+        Ok(())
+    }
+
+    // the trailer is written when the encoder is dropped
+    fn finish(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub struct Apng<'a> {
+    clock: EmuClock,
+    scale: u32,
+    elapsed: u64,
+    writer: png::Writer<&'a mut Vec<u8>>,
+}
+
+impl<'a> Apng<'a> {
+    // apng needs the number of frames before the first one
+    pub fn new(
+        bytes: &'a mut Vec<u8>,
+        clock: EmuClock,
+        scale: u32,
+        frames: u32,
+    ) -> Result<Apng<'a>> {
+        let mut encoder = png::Encoder::new(bytes, WIDTH * scale, HEIGHT * scale);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(frames, 0)?;
+
+        Ok(Apng {
+            clock,
+            scale,
+            elapsed: 0,
+            writer: encoder.write_header()?,
+        })
+    }
+}
+
+impl AnimEncoder for Apng<'_> {
+    fn push_frame(&mut self, rgba: &mut [u8], frames: u64) -> Result<()> {
+        let millis = self.clock.millis(self.elapsed + frames) - self.clock.millis(self.elapsed);
+        self.elapsed += frames;
+
+        self.writer.set_frame_delay(millis as u16, 1000)?;
+        self.writer.write_image_data(&upscale(rgba, self.scale))?;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+// frames that end up in the animation: every `step`th one and always the last
+fn shown(len: usize, step: u64) -> impl Iterator<Item = usize> {
+    (1..=len).filter(move |&end| end as u64 % step == 0 || end == len)
+}
+
+fn encode_with(mut encoder: impl AnimEncoder, frames: &mut [Vec<u8>], step: u64) -> Result<()> {
+    let mut last = 0;
+    for end in shown(frames.len(), step) {
+        encoder.push_frame(&mut frames[end - 1], (end - last) as u64)?;
+        last = end;
+    }
+    encoder.finish()
+}
+
+fn encode_at(
+    format: AnimFormat,
+    frames: &mut [Vec<u8>],
+    clock: EmuClock,
+    scale: u32,
+    step: u64,
+) -> Result<File> {
+    let mut bytes = Vec::new();
+    match format {
+        AnimFormat::Gif => encode_with(Gif::new(&mut bytes, clock, scale)?, frames, step)?,
+        AnimFormat::Apng => {
+            let count = shown(frames.len(), step).count() as u32;
+            encode_with(Apng::new(&mut bytes, clock, scale, count)?, frames, step)?
+        }
+    }
+
+    let (name, typ) = match format {
+        AnimFormat::Gif => ("frames.gif", "image/gif"),
+        AnimFormat::Apng => ("frames.png", "image/png"),
+    };
+    Ok(File {
+        name: name.into(),
+        typ: typ.into(),
+        data: bytes.into(),
+    })
+}
+
+// drops frames and then detail until the animation fits in an upload,
+// only the raw frames are kept around for another try
+pub fn encode(
+    format: AnimFormat,
+    frames: &mut [Vec<u8>],
+    clock: EmuClock,
+    mut scale: u32,
+) -> Result<File> {
+    let mut step = format.step();
+    let mut file = encode_at(format, frames, clock, scale, step)?;
+    while file.data.len() > ATTACHMENT_LIMIT && (step < MAX_STEP || scale > 1) {
+        eprintln!(
+            "{} frame animation is {} bytes at {scale}x and 1/{step} frames, shrinking it",
+            frames.len(),
+            file.data.len()
+        );
+        if step < MAX_STEP {
+            step *= 2;
+        } else {
+            scale -= 1;
+        }
+        file = encode_at(format, frames, clock, scale, step)?;
+    }
+    Ok(file)
+}
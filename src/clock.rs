@@ -0,0 +1,165 @@
+use std::time::Duration;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+}
+
+impl Region {
+    pub fn from_ines(rom: &[u8]) -> Region {
+        if rom.len() < 16 || &rom[0..4] != b"NES\x1a" {
+            return Region::Ntsc;
+        }
+
+        if rom[7] & 0x0c == 0x08 {
+            // NES 2.0: CPU/PPU timing in byte 12
+            match rom[12] & 0x03 {
+                1 => Region::Pal,
+                _ => Region::Ntsc,
+            }
+        } else if rom[9] & 0x01 == 1 {
+            Region::Pal
+        } else {
+            Region::Ntsc
+        }
+    }
+
+    // length of a single frame in seconds, as an exact fraction
+    // NTSC: 357366 master cycles per frame at 236.25 / 11 MHz
+    // PAL: 531960 master cycles per frame at 26.6017125 MHz
+    fn frame_period(self) -> (u128, u128) {
+        match self {
+            Region::Ntsc => (11 * 357366, 236_250_000),
+            Region::Pal => (2 * 531960, 53_203_425),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EmuClock {
+    pub region: Region,
+}
+
+impl EmuClock {
+    pub fn new(region: Region) -> EmuClock {
+        EmuClock { region }
+    }
+
+    // whole frames that fit in the given duration
+    pub fn frames(&self, duration: Duration) -> u64 {
+        let (num, den) = self.region.frame_period();
+        (duration.as_nanos() * den / (num * 1_000_000_000)) as u64
+    }
+
+    // rounded to the nearest centisecond, the resolution of GIF delays
+    pub fn centis(&self, frames: u64) -> u64 {
+        let (num, den) = self.region.frame_period();
+        ((frames as u128 * num * 200 + den) / (den * 2)) as u64
+    }
+
+    // rounded to the nearest millisecond, for formats with finer delays
+    pub fn millis(&self, frames: u64) -> u64 {
+        let (num, den) = self.region.frame_period();
+        ((frames as u128 * num * 2000 + den) / (den * 2)) as u64
+    }
+
+    // centiseconds of a GIF frame covering `frames` frames starting at frame `start`
+    // computed from absolute timestamps so rounding errors never accumulate
+    pub fn gif_delay(&self, start: u64, frames: u64) -> u16 {
+        (self.centis(start + frames) - self.centis(start)) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NTSC: EmuClock = EmuClock {
+        region: Region::Ntsc,
+    };
+    const PAL: EmuClock = EmuClock {
+        region: Region::Pal,
+    };
+
+    // iNES header with the given flags 7, 9 and 12
+    fn header(flags7: u8, flags9: u8, flags12: u8) -> Vec<u8> {
+        let mut rom = b"NES\x1a".to_vec();
+        rom.resize(16, 0);
+        rom[7] = flags7;
+        rom[9] = flags9;
+        rom[12] = flags12;
+        rom
+    }
+
+    #[test]
+    fn frames_at_large_durations() {
+        // 60.0988 fps, a flat 60 would be off by 6 minutes a day
+        assert_eq!(NTSC.frames(Duration::from_secs(3600)), 216_355);
+        assert_eq!(NTSC.frames(Duration::from_secs(86_400)), 5_192_537);
+    }
+
+    #[test]
+    fn durations_at_large_frame_counts() {
+        assert_eq!(NTSC.centis(1_000_000), 1_663_926);
+        assert_eq!(NTSC.millis(1_000_000), 16_639_263);
+    }
+
+    #[test]
+    fn gif_delays_do_not_drift() {
+        // an hour of gif frames that each cover two emulated frames
+        let frames = 216_354;
+        let total: u64 = (0..frames)
+            .step_by(2)
+            .map(|start| NTSC.gif_delay(start, 2) as u64)
+            .sum();
+        assert_eq!(total, NTSC.centis(frames));
+    }
+
+    #[test]
+    fn pal_conversions() {
+        // 50.007 fps
+        assert_eq!(PAL.frames(Duration::from_secs(3600)), 180_025);
+        assert_eq!(PAL.frames(Duration::from_secs(86_400)), 4_320_602);
+        assert_eq!(PAL.centis(1_000_000), 1_999_721);
+        assert_eq!(PAL.millis(1_000_000), 19_997_209);
+    }
+
+    #[test]
+    fn pal_gif_delays() {
+        // two PAL frames are 40ms where two NTSC frames are 33ms
+        assert_eq!(PAL.gif_delay(0, 2), 4);
+        assert_eq!(NTSC.gif_delay(0, 2), 3);
+
+        let frames = 180_024;
+        let total: u64 = (0..frames)
+            .step_by(2)
+            .map(|start| PAL.gif_delay(start, 2) as u64)
+            .sum();
+        assert_eq!(total, PAL.centis(frames));
+    }
+
+    #[test]
+    fn region_from_ines() {
+        assert_eq!(Region::from_ines(&header(0, 0, 0)), Region::Ntsc);
+        assert_eq!(Region::from_ines(&header(0, 1, 0)), Region::Pal);
+    }
+
+    #[test]
+    fn region_from_nes2() {
+        assert_eq!(Region::from_ines(&header(0x08, 0, 0)), Region::Ntsc);
+        assert_eq!(Region::from_ines(&header(0x08, 0, 1)), Region::Pal);
+        // multi-region and Dendy play as NTSC
+        assert_eq!(Region::from_ines(&header(0x08, 0, 2)), Region::Ntsc);
+        assert_eq!(Region::from_ines(&header(0x08, 0, 3)), Region::Ntsc);
+        // NES 2.0 ignores the iNES region bit
+        assert_eq!(Region::from_ines(&header(0x08, 1, 0)), Region::Ntsc);
+    }
+
+    #[test]
+    fn region_of_other_files() {
+        assert_eq!(Region::from_ines(b"not a rom"), Region::Ntsc);
+        assert_eq!(Region::from_ines(&header(0, 1, 0)[..15]), Region::Ntsc);
+    }
+}
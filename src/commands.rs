@@ -0,0 +1,275 @@
+use crate::render::StillFormat;
+use crate::replay::INTERNAL_RAM;
+use discord::command::{CommandData, CommandOption, CreateCommand, CreateOption, OptionValue};
+use discord::interaction::CreateReply;
+use discord::message::{Attachment, MessageFlags};
+use discord::request::{Bot, Result};
+use discord::resource::Snowflake;
+use discord::user::User;
+
+#[derive(Clone, PartialEq, Eq)]
+pub enum Command {
+    Start,
+    Invite(Snowflake<User>),
+    Uninvite(Option<Snowflake<User>>),
+    Export,
+    Poke {
+        address: String,
+        value: String,
+    },
+    Load {
+        filename: String,
+        url: String,
+        size: u64,
+    },
+    Games(Option<String>),
+    Advance(i64),
+    Script(String),
+    Stats,
+    Log,
+    Echo(bool),
+    Format {
+        format: String,
+        quality: Option<i64>,
+    },
+}
+
+fn nes() -> CreateCommand {
+    CreateCommand::new("nes", "Play the NES").options(vec![
+        CreateOption::subcommand("start", "Start a game in this channel"),
+        CreateOption::subcommand("load", "Upload a ROM and start it in this channel").options(
+            vec![CreateOption::attachment("rom", "iNES file (.nes)").required(true)],
+        ),
+        CreateOption::subcommand("games", "List uploaded ROMs or start one").options(vec![
+            CreateOption::string("name", "Game to start, lists them if left empty"),
+        ]),
+        CreateOption::subcommand("advance", "Run an exact number of frames").options(vec![
+            CreateOption::integer("frames", "Frames to run, 1 to 600").required(true),
+        ]),
+        CreateOption::subcommand("script", "Play a sequence of inputs").options(vec![
+            CreateOption::string(
+                "inputs",
+                "Buttons (A B S=select T=start U D L R, . for none) and frames: R*30 A+R*10 .*5",
+            )
+            .required(true),
+        ]),
+        CreateOption::subcommand("echo", "Confirm your button presses privately").options(vec![
+            CreateOption::boolean("enabled", "Whether your presses are echoed").required(true),
+        ]),
+        CreateOption::subcommand("format", "Change the image format of the frames").options(vec![
+            CreateOption::string("format", "png, jpeg or webp").required(true),
+            CreateOption::integer("quality", "Quality of jpeg and webp, 1 to 100"),
+        ]),
+        CreateOption::subcommand("stats", "Show who has been playing this game"),
+        CreateOption::subcommand("log", "Download the recent actions of this game as CSV"),
+        CreateOption::subcommand("export", "Download this game as an FCEUX movie"),
+        CreateOption::subcommand("poke", "Write a byte into the NES memory").options(vec![
+            CreateOption::string("address", "Address in hex (0x075a) or decimal").required(true),
+            CreateOption::string("value", "Byte in hex (0x09) or decimal").required(true),
+        ]),
+        CreateOption::subcommand("invite", "Let someone else play your game").options(vec![
+            CreateOption::user("user", "Player to invite").required(true),
+        ]),
+        CreateOption::subcommand("uninvite", "Take away someone's access to your game").options(
+            vec![CreateOption::user(
+                "user",
+                "Player to remove, everyone if left empty",
+            )],
+        ),
+    ])
+}
+
+pub async fn register(client: &Bot) -> Result<()> {
+    let application = client.application().await?;
+    application
+        .overwrite_global_commands(client, vec![nes()])
+        .await?;
+    Ok(())
+}
+
+fn option<'a>(subcommand: &'a CommandOption, name: &str) -> Option<&'a OptionValue> {
+    subcommand
+        .options
+        .iter()
+        .find(|option| option.name == name)
+        .map(|option| &option.value)
+}
+
+fn string(subcommand: &CommandOption, name: &str) -> Option<String> {
+    match option(subcommand, name)? {
+        OptionValue::String(string) => Some(string.clone()),
+        _ => None,
+    }
+}
+
+fn boolean(subcommand: &CommandOption, name: &str) -> Option<bool> {
+    match option(subcommand, name)? {
+        OptionValue::Boolean(boolean) => Some(*boolean),
+        _ => None,
+    }
+}
+
+fn integer(subcommand: &CommandOption, name: &str) -> Option<i64> {
+    match option(subcommand, name)? {
+        OptionValue::Integer(integer) => Some(*integer),
+        _ => None,
+    }
+}
+
+fn user(subcommand: &CommandOption, name: &str) -> Option<Snowflake<User>> {
+    match option(subcommand, name)? {
+        OptionValue::User(user) => Some(*user),
+        _ => None,
+    }
+}
+
+fn attachment<'a>(
+    data: &'a CommandData,
+    subcommand: &CommandOption,
+    name: &str,
+) -> Option<&'a Attachment> {
+    match option(subcommand, name)? {
+        OptionValue::Attachment(id) => data.resolved.as_ref()?.attachments.get(id),
+        _ => None,
+    }
+}
+
+pub fn parse(data: &CommandData) -> Option<Command> {
+    if data.name != "nes" {
+        return None;
+    }
+    let subcommand = data.options.first()?;
+    match subcommand.name.as_str() {
+        "start" => Some(Command::Start),
+        "load" => {
+            let rom = attachment(data, subcommand, "rom")?;
+            Some(Command::Load {
+                filename: rom.filename.clone(),
+                url: rom.url.clone(),
+                size: rom.size,
+            })
+        }
+        "games" => Some(Command::Games(string(subcommand, "name"))),
+        "advance" => Some(Command::Advance(integer(subcommand, "frames")?)),
+        "script" => Some(Command::Script(string(subcommand, "inputs")?)),
+        "echo" => Some(Command::Echo(boolean(subcommand, "enabled")?)),
+        "format" => Some(Command::Format {
+            format: string(subcommand, "format")?,
+            quality: integer(subcommand, "quality"),
+        }),
+        "stats" => Some(Command::Stats),
+        "log" => Some(Command::Log),
+        "export" => Some(Command::Export),
+        "poke" => Some(Command::Poke {
+            address: string(subcommand, "address")?,
+            value: string(subcommand, "value")?,
+        }),
+        "invite" => Some(Command::Invite(user(subcommand, "user")?)),
+        "uninvite" => Some(Command::Uninvite(user(subcommand, "user"))),
+        _ => None,
+    }
+}
+
+// for the activity log
+pub fn name(data: &CommandData) -> String {
+    match data.options.first() {
+        Some(subcommand) => format!("/{} {}", data.name, subcommand.name),
+        None => format!("/{}", data.name),
+    }
+}
+
+fn number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix('$')) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// internal RAM is the only memory that is safe to write without side effects
+// and the only memory a replay can write back, so there is no unsafe option for the bus
+pub fn poke(address: &str, value: &str) -> std::result::Result<(u16, u8), String> {
+    let address = number(address)
+        .and_then(|address| u16::try_from(address).ok())
+        .ok_or(format!("{address} is not an address"))?;
+    let value = number(value)
+        .and_then(|value| u8::try_from(value).ok())
+        .ok_or(format!("{value} is not a byte"))?;
+    if address >= INTERNAL_RAM {
+        return Err(format!(
+            "{address:#06x} is outside internal RAM ($0000-$07ff)"
+        ));
+    }
+    Ok((address, value))
+}
+
+// the lossy formats default to the quality STILL_FORMAT does
+pub fn still(format: &str, quality: Option<i64>) -> std::result::Result<StillFormat, String> {
+    let quality = match quality {
+        None => 80,
+        Some(quality @ 1..=100) => quality as u8,
+        Some(quality) => return Err(format!("quality {quality} is not between 1 and 100")),
+    };
+    match format.trim().to_lowercase().as_str() {
+        "png" => Ok(StillFormat::Png),
+        "jpeg" | "jpg" => Ok(StillFormat::Jpeg(quality)),
+        "webp" => Ok(StillFormat::WebP(quality)),
+        _ => Err(format!("{format} is not supported, use png, jpeg or webp")),
+    }
+}
+
+pub fn ephemeral(content: impl Into<String>) -> CreateReply {
+    CreateReply::default()
+        .content(content.into())
+        .flags(MessageFlags::EPHEMERAL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pokes_hex_and_decimal() {
+        assert_eq!(poke("0x075a", "0x09"), Ok((0x075a, 0x09)));
+        assert_eq!(poke("$075a", "9"), Ok((0x075a, 0x09)));
+        assert_eq!(poke("1882", "255"), Ok((0x075a, 0xff)));
+        assert_eq!(poke("0x07ff", "0"), Ok((0x07ff, 0x00)));
+    }
+
+    #[test]
+    fn refuses_bad_numbers() {
+        assert_eq!(poke("lives", "9"), Err("lives is not an address".into()));
+        assert_eq!(
+            poke("0x10000", "9"),
+            Err("0x10000 is not an address".into())
+        );
+        assert_eq!(poke("0x075a", "256"), Err("256 is not a byte".into()));
+        assert_eq!(poke("0x075a", "-1"), Err("-1 is not a byte".into()));
+    }
+
+    #[test]
+    fn refuses_addresses_outside_internal_ram() {
+        assert_eq!(
+            poke("0x0800", "9"),
+            Err("0x0800 is outside internal RAM ($0000-$07ff)".into())
+        );
+        assert_eq!(
+            poke("$2000", "9"),
+            Err("0x2000 is outside internal RAM ($0000-$07ff)".into())
+        );
+    }
+
+    #[test]
+    fn picks_still_formats() {
+        assert_eq!(still("png", None), Ok(StillFormat::Png));
+        assert_eq!(still("WebP", None), Ok(StillFormat::WebP(80)));
+        assert_eq!(still("jpg", Some(60)), Ok(StillFormat::Jpeg(60)));
+        assert_eq!(
+            still("gif", None),
+            Err("gif is not supported, use png, jpeg or webp".into())
+        );
+        assert_eq!(
+            still("webp", Some(0)),
+            Err("quality 0 is not between 1 and 100".into())
+        );
+    }
+}
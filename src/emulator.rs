@@ -0,0 +1,226 @@
+use crate::anim;
+use crate::clock::EmuClock;
+use crate::error::{Error, Result};
+use crate::fm2;
+use crate::frame::frame_to_rgba;
+use crate::ram_watch;
+use crate::render::{as_still, StillFormat};
+use crate::replay::InputLog;
+use crate::schedule::Schedule;
+use crate::Config;
+use discord::request::File;
+use fastnes::ppu::DrawOptions;
+use fastnes::{input::Controllers, nes::NES, ppu::FastPPU};
+use std::fs;
+use std::mem;
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use tokio::sync::oneshot;
+
+// every frame of an advance is kept until it is animated, ~245KB each at 1x,
+// so longer advances and auto-run catch-ups are cut short
+const MAX_FRAMES: u64 = 600;
+
+pub struct Emulator {
+    config: Arc<Config>,
+    nes: NES,
+    // buttons held by the players
+    input: Arc<AtomicU8>,
+    // what the controller actually sees this frame
+    pad: Arc<AtomicU8>,
+    clock: EmuClock,
+    boot: InputLog,
+    pub log: InputLog,
+    pub layers: DrawOptions,
+    pub still: StillFormat,
+    pub turbo: u8,
+}
+
+// a fresh NES from the ROM, booted by the profile
+// a soft reset would keep internal RAM and with it every earlier poke
+fn power_on(config: &Config, pad: &Arc<AtomicU8>) -> (NES, InputLog) {
+    let controllers = Controllers::standard(pad);
+    let rom = config.patched.as_ref().unwrap_or(&config.rom);
+    let mut nes = NES::read_ines(rom, controllers, FastPPU::new());
+
+    let mut boot = InputLog::default();
+    config.profile.boot(&mut nes, pad, &mut boot);
+    (nes, boot)
+}
+
+impl Emulator {
+    // boots the game and replays `log` on top of it
+    fn new(config: Arc<Config>, input: Arc<AtomicU8>, log: InputLog) -> Emulator {
+        let pad = Arc::new(AtomicU8::new(0));
+        let (mut nes, boot) = power_on(&config, &pad);
+        let clock = EmuClock::new(config.region);
+        let still = config.still;
+        log.replay(&mut nes, &pad);
+
+        Emulator {
+            config,
+            nes,
+            input,
+            pad,
+            clock,
+            boot,
+            log,
+            layers: DrawOptions::All,
+            still,
+            turbo: 0,
+        }
+    }
+
+    fn step(&mut self) {
+        // turbo buttons alternate every frame instead of following the held ones
+        let held = self.input.load(Ordering::Relaxed);
+        let turbo = if self.log.frames() % 2 == 0 {
+            self.turbo
+        } else {
+            0
+        };
+        self.pad
+            .store(held & !self.turbo | turbo, Ordering::Relaxed);
+        self.log.step(&mut self.nes, &self.pad);
+    }
+
+    fn reboot(&mut self) {
+        (self.nes, self.boot) = power_on(&self.config, &self.pad);
+    }
+
+    // runs at least `frames` frames and animates them
+    pub fn advance(
+        &mut self,
+        frames: u64,
+        until_ready: bool,
+        mut schedule: Schedule,
+    ) -> Result<File> {
+        let mut shown = Vec::new();
+        let mut elapsed = 0;
+        let frames = frames.max(schedule.last()).min(MAX_FRAMES);
+
+        while elapsed < frames
+            || until_ready && elapsed < MAX_FRAMES && !self.config.profile.is_input_ready(&self.nes)
+        {
+            schedule.release(elapsed, &self.input);
+            self.step();
+            elapsed += 1;
+
+            let frame = self.nes.draw_frame(self.layers);
+            shown.push(frame_to_rgba(&frame, None));
+        }
+
+        self.animate(shown)
+    }
+
+    // plays every step frame by frame, then gives the players their buttons back
+    pub fn script(&mut self, steps: Vec<(u8, u64)>) -> Result<File> {
+        let held = self.input.load(Ordering::Relaxed);
+        let mut shown = Vec::new();
+        for (byte, frames) in steps {
+            self.input.store(byte, Ordering::Relaxed);
+            for _ in 0..frames {
+                self.step();
+                let frame = self.nes.draw_frame(self.layers);
+                shown.push(frame_to_rgba(&frame, None));
+            }
+        }
+        self.input.store(held, Ordering::Relaxed);
+
+        self.animate(shown)
+    }
+
+    // a single frame is not worth animating
+    fn animate(&mut self, mut shown: Vec<Vec<u8>>) -> Result<File> {
+        if shown.len() < 2 {
+            return self.still();
+        }
+        anim::encode(self.config.anim, &mut shown, self.clock, self.config.scale)
+    }
+
+    // a single frame, for one-shot presses
+    pub fn tap(&mut self) -> Result<File> {
+        self.step();
+        self.still()
+    }
+
+    // writes a byte before the next frame, returning the old one
+    pub fn poke(&mut self, address: u16, value: u8) -> u8 {
+        self.log.poke(&mut self.nes, address, value)
+    }
+
+    pub fn watch(&self) -> Option<String> {
+        ram_watch::render(self.config.profile.watches(), &self.nes)
+    }
+
+    pub fn still(&mut self) -> Result<File> {
+        let frame = self.nes.draw_frame(self.layers);
+        as_still(&frame, self.still, self.config.scale)
+    }
+
+    pub fn reset(&mut self) {
+        self.reboot();
+        self.log = InputLog::default();
+        self.input.store(0, Ordering::Relaxed);
+        self.turbo = 0;
+    }
+
+    // every input since power on, boot included
+    pub fn movie(&self) -> Result<File> {
+        let path = &self.config.rom;
+        let image = fs::read(path).map_err(|err| Error::Rom(path.clone(), err))?;
+        let rom = Path::new(path).file_stem().unwrap();
+        Ok(fm2::export(
+            &rom.to_string_lossy(),
+            &image,
+            &[&self.boot, &self.log],
+        ))
+    }
+
+    // back to an earlier frame of the log, replaying everything before it
+    pub fn rewind(&mut self, frames: u64) {
+        let mut log = mem::take(&mut self.log);
+        log.truncate(frames);
+        self.load(log);
+    }
+
+    // rebuild the state by replaying every input since boot
+    pub fn load(&mut self, log: InputLog) {
+        self.reboot();
+        log.replay(&mut self.nes, &self.pad);
+        self.log = log;
+    }
+}
+
+type Job = Box<dyn FnOnce(&mut Emulator) + Send>;
+
+// NES is not Send, so every emulator lives on its own thread and is driven through jobs
+pub struct EmuHandle {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl EmuHandle {
+    pub fn spawn(config: Arc<Config>, input: Arc<AtomicU8>, log: InputLog) -> EmuHandle {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        thread::spawn(move || {
+            let mut emu = Emulator::new(config, input, log);
+            for job in receiver {
+                job(&mut emu);
+            }
+        });
+        EmuHandle { jobs }
+    }
+
+    pub fn run<R: Send + 'static>(
+        &self,
+        job: impl FnOnce(&mut Emulator) -> R + Send + 'static,
+    ) -> oneshot::Receiver<R> {
+        let (sender, receiver) = oneshot::channel();
+        let _ = self.jobs.send(Box::new(move |emu| {
+            let _ = sender.send(job(emu));
+        }));
+        receiver
+    }
+}
@@ -0,0 +1,88 @@
+use discord::request::Error as RequestError;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+use tokio::time;
+
+pub enum Error {
+    Discord(RequestError),
+    Rom(String, io::Error),
+    MissingEnv(&'static str),
+    // an environment variable that is set but unusable
+    Config(String),
+    Image(image::ImageError),
+    Gif(gif::EncodingError),
+    Apng(png::EncodingError),
+    // the emulator thread panicked and took its game with it
+    Emulator,
+    // stop was pressed during a job, nothing after it should touch the message
+    Stopped,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Discord(err) => write!(f, "discord request failed: {err}"),
+            Error::Rom(path, err) => write!(f, "could not read ROM {path}: {err}"),
+            Error::MissingEnv(name) => write!(f, "environment variable {name} must be set"),
+            Error::Config(msg) => write!(f, "{msg}"),
+            Error::Image(err) => write!(f, "could not encode the frame: {err}"),
+            Error::Gif(err) => write!(f, "could not encode the GIF: {err}"),
+            Error::Apng(err) => write!(f, "could not encode the APNG: {err}"),
+            Error::Emulator => write!(f, "the emulator crashed"),
+            Error::Stopped => write!(f, "the game was stopped"),
+        }
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl From<RequestError> for Error {
+    fn from(err: RequestError) -> Self {
+        Error::Discord(err)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(err: image::ImageError) -> Self {
+        Error::Image(err)
+    }
+}
+
+impl From<gif::EncodingError> for Error {
+    fn from(err: gif::EncodingError) -> Self {
+        Error::Gif(err)
+    }
+}
+
+impl From<png::EncodingError> for Error {
+    fn from(err: png::EncodingError) -> Self {
+        Error::Apng(err)
+    }
+}
+
+// rate limits are only retried this often before giving up
+const RATE_LIMIT_RETRIES: u32 = 3;
+
+// waits out 429s for as long as discord asks, anything else is returned right away
+pub async fn retry<T, F: Future<Output = discord::request::Result<T>>>(
+    mut request: impl FnMut() -> F,
+) -> discord::request::Result<T> {
+    let mut retries = 0;
+    loop {
+        match request().await {
+            Err(RequestError::RateLimited(limit)) if retries < RATE_LIMIT_RETRIES => {
+                retries += 1;
+                time::sleep(Duration::from_secs_f64(limit.retry_after)).await;
+            }
+            result => return result,
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use crate::replay::InputLog;
+use discord::request::File;
+
+// controller bits from high to low, in the order fm2 writes them
+const BUTTONS: &str = "RLDUTSBA";
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+// FCEUX hashes PRG and CHR only, without the header and trainer
+pub fn checksum(image: &[u8]) -> String {
+    let start = if image.get(6).is_some_and(|flags| flags & 0x04 != 0) {
+        16 + 512
+    } else {
+        16
+    };
+    let size = image.get(4).map_or(0, |&prg| prg as usize * 16 * 1024)
+        + image.get(5).map_or(0, |&chr| chr as usize * 8 * 1024);
+    let data = image.get(start..).unwrap_or_default();
+    let digest = md5::compute(&data[..size.min(data.len())]);
+    format!("base64:{}", base64(&digest.0))
+}
+
+// FCEUX text movie that starts at power on
+pub fn export(rom: &str, image: &[u8], logs: &[&InputLog]) -> File {
+    File {
+        name: format!("{rom}.fm2"),
+        typ: "text/plain".into(),
+        data: movie(rom, image, logs).into_bytes().into(),
+    }
+}
+
+fn movie(rom: &str, image: &[u8], logs: &[&InputLog]) -> String {
+    let checksum = checksum(image);
+    let frames: u64 = logs.iter().map(|log| log.frames()).sum();
+    let mut movie = format!(
+        "version 3\n\
+         emuVersion 22020\n\
+         rerecordCount 0\n\
+         palFlag 0\n\
+         romFilename {rom}\n\
+         romChecksum {checksum}\n\
+         guid 00000000-0000-0000-0000-000000000000\n\
+         fourscore 0\n\
+         microphone 0\n\
+         port0 1\n\
+         port1 0\n\
+         port2 0\n\
+         FDS 0\n\
+         NewPPU 0\n\
+         comment {frames} frames played on DiscordNES\n"
+    );
+    for input in logs.iter().flat_map(|log| log.inputs()) {
+        let pad: String = BUTTONS
+            .chars()
+            .enumerate()
+            .map(|(i, button)| if input & 0x80 >> i != 0 { button } else { '.' })
+            .collect();
+        movie.push_str(&format!("|0|{pad}|||\n"));
+    }
+    movie
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+    }
+
+    #[test]
+    fn checksum_skips_header() {
+        // md5 of nothing, the same however the header looks
+        assert_eq!(
+            checksum(b"NES\x1a\0\0\0\0\0\0\0\0\0\0\0\0"),
+            "base64:1B2M2Y8AsgTpgAmY7PhCfg=="
+        );
+        let mut image = b"NES\x1a\x01\0\0\0\0\0\0\0\0\0\0\0".to_vec();
+        image.extend([0; 16 * 1024]);
+        let prg = checksum(&image);
+        image[7] = 0xff;
+        image.extend(b"trailing garbage");
+        assert_eq!(checksum(&image), prg);
+    }
+
+    #[test]
+    fn format() {
+        let boot = InputLog::from_inputs(&[0x00, 0x08]);
+        let log = InputLog::from_inputs(&[0x81, 0x42]);
+        let image = b"NES\x1a\0\0\0\0\0\0\0\0\0\0\0\0";
+        assert_eq!(
+            movie("smb", image, &[&boot, &log]),
+            "version 3\n\
+             emuVersion 22020\n\
+             rerecordCount 0\n\
+             palFlag 0\n\
+             romFilename smb\n\
+             romChecksum base64:1B2M2Y8AsgTpgAmY7PhCfg==\n\
+             guid 00000000-0000-0000-0000-000000000000\n\
+             fourscore 0\n\
+             microphone 0\n\
+             port0 1\n\
+             port1 0\n\
+             port2 0\n\
+             FDS 0\n\
+             NewPPU 0\n\
+             comment 4 frames played on DiscordNES\n\
+             |0|........|||\n\
+             |0|....T...|||\n\
+             |0|R......A|||\n\
+             |0|.L....B.|||\n"
+        );
+    }
+}
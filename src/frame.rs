@@ -0,0 +1,117 @@
+use fastnes::ppu::Color;
+use std::borrow::Cow;
+
+pub const WIDTH: u32 = 256;
+pub const HEIGHT: u32 = 240;
+
+pub type Frame = [Color; (WIDTH * HEIGHT) as usize];
+
+// pixels matching `key` become fully transparent
+pub fn frame_to_rgba(frame: &Frame, key: Option<[u8; 3]>) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity(frame.len() * 4);
+    for color in frame {
+        let rgb = [color.r, color.g, color.b];
+        rgba.extend(rgb);
+        rgba.push(if Some(rgb) == key { 0 } else { 255 });
+    }
+    rgba
+}
+
+// nearest neighbor, a scale of 1 hands the pixels back untouched
+pub fn upscale(rgba: &[u8], scale: u32) -> Cow<'_, [u8]> {
+    if scale == 1 {
+        return Cow::Borrowed(rgba);
+    }
+    let scale = scale as usize;
+    let mut scaled = Vec::with_capacity(rgba.len() * scale * scale);
+    for row in rgba.chunks_exact(WIDTH as usize * 4) {
+        let start = scaled.len();
+        for pixel in row.chunks_exact(4) {
+            for _ in 0..scale {
+                scaled.extend_from_slice(pixel);
+            }
+        }
+        let end = scaled.len();
+        for _ in 1..scale {
+            scaled.extend_from_within(start..end);
+        }
+    }
+    Cow::Owned(scaled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fastnes::ppu::Color;
+
+    const RED: Color = Color { r: 255, g: 0, b: 0 };
+    const BLUE: Color = Color { r: 0, g: 0, b: 255 };
+
+    // left half red, right half blue
+    fn frame() -> Box<Frame> {
+        let mut frame = Box::new([RED; (WIDTH * HEIGHT) as usize]);
+        for row in frame.chunks_exact_mut(WIDTH as usize) {
+            row[WIDTH as usize / 2..].fill(BLUE);
+        }
+        frame
+    }
+
+    #[test]
+    fn rgba_byte_order() {
+        let rgba = frame_to_rgba(&frame(), None);
+        assert_eq!(rgba.len(), (WIDTH * HEIGHT * 4) as usize);
+        assert_eq!(rgba[..4], [255, 0, 0, 255]);
+        let right = (WIDTH as usize - 1) * 4;
+        assert_eq!(rgba[right..right + 4], [0, 0, 255, 255]);
+        let last = rgba.len() - 4;
+        assert_eq!(rgba[last..], [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn key_becomes_transparent() {
+        let rgba = frame_to_rgba(&frame(), Some([0, 0, 255]));
+        assert_eq!(rgba[..4], [255, 0, 0, 255]);
+        let right = (WIDTH as usize - 1) * 4;
+        assert_eq!(rgba[right..right + 4], [0, 0, 255, 0]);
+    }
+
+    #[test]
+    fn scale_one_borrows() {
+        let rgba = frame_to_rgba(&frame(), None);
+        assert!(matches!(upscale(&rgba, 1), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn upscale_duplicates_rows_and_columns() {
+        let mut rgba = frame_to_rgba(&frame(), None);
+        // a single green pixel in the top left corner
+        rgba[..4].copy_from_slice(&[0, 255, 0, 255]);
+
+        let scaled = upscale(&rgba, 3);
+        let stride = WIDTH as usize * 3 * 4;
+        assert_eq!(scaled.len(), rgba.len() * 9);
+        for y in 0..3 {
+            for x in 0..3 {
+                let at = y * stride + x * 4;
+                assert_eq!(scaled[at..at + 4], [0, 255, 0, 255]);
+            }
+            // the pixel next to it is still red
+            let at = y * stride + 3 * 4;
+            assert_eq!(scaled[at..at + 4], [255, 0, 0, 255]);
+        }
+        // the fourth row starts at the second source row
+        assert_eq!(scaled[3 * stride..3 * stride + 4], [255, 0, 0, 255]);
+        // columns from the blue half end up in the right half
+        let right = stride - 4;
+        assert_eq!(scaled[right..right + 4], [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn upscale_sizes() {
+        let rgba = frame_to_rgba(&frame(), None);
+        for scale in 1..=4 {
+            let rows = upscale(&rgba, scale).len() / (WIDTH * scale * 4) as usize;
+            assert_eq!(rows, (HEIGHT * scale) as usize, "scale {scale}");
+        }
+    }
+}
@@ -0,0 +1,169 @@
+// game genie letters in nibble order
+const LETTERS: &str = "APZLGITYEOXUKSVN";
+
+pub struct Code {
+    pub code: String,
+    address: u16,
+    value: u8,
+    compare: Option<u8>,
+}
+
+impl Code {
+    fn decode(code: &str) -> Option<Code> {
+        let n: Vec<u16> = code
+            .chars()
+            .map(|c| LETTERS.find(c.to_ascii_uppercase()).map(|n| n as u16))
+            .collect::<Option<_>>()?;
+        if n.len() != 6 && n.len() != 8 {
+            return None;
+        }
+
+        let address = 0x8000
+            | (n[3] & 7) << 12
+            | (n[5] & 7) << 8
+            | (n[4] & 8) << 8
+            | (n[2] & 7) << 4
+            | (n[1] & 8) << 4
+            | n[4] & 7
+            | n[3] & 8;
+        // the last letter of the value is the 6th or the 8th
+        let value = ((n[1] & 7) << 4 | (n[0] & 8) << 4 | n[0] & 7) as u8;
+        let (value, compare) = match n.len() {
+            6 => (value | (n[5] & 8) as u8, None),
+            _ => {
+                let compare = (n[7] & 7) << 4 | (n[6] & 8) << 4 | n[6] & 7 | n[5] & 8;
+                (value | (n[7] & 8) as u8, Some(compare as u8))
+            }
+        };
+
+        Some(Code {
+            code: code.to_ascii_uppercase(),
+            address,
+            value,
+            compare,
+        })
+    }
+}
+
+// codes separated by commas or spaces
+pub fn decode_all(codes: &str) -> Result<Vec<Code>, String> {
+    let codes: Vec<&str> = codes
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|code| !code.is_empty())
+        .collect();
+    let invalid: Vec<&str> = codes
+        .iter()
+        .copied()
+        .filter(|code| Code::decode(code).is_none())
+        .collect();
+    if !invalid.is_empty() {
+        return Err(format!("invalid Game Genie codes: {}", invalid.join(", ")));
+    }
+    Ok(codes.into_iter().filter_map(Code::decode).collect())
+}
+
+// patches the PRG ROM of an iNES image in place
+pub fn patch(rom: &mut [u8], codes: &[Code]) -> Result<(), String> {
+    if rom.len() < 16 {
+        return Err("the ROM has no iNES header".into());
+    }
+    let trainer = if rom[6] & 0x04 != 0 { 512 } else { 0 };
+    let start = 16 + trainer;
+    let len = rom[4] as usize * 0x4000;
+    if len == 0 || rom.len() < start + len {
+        return Err("the ROM is shorter than its header says".into());
+    }
+    let prg = &mut rom[start..start + len];
+
+    for code in codes {
+        let offset = code.address as usize - 0x8000;
+        let banks: Vec<usize> = if prg.len() <= 0x8000 {
+            // fixed PRG, mirrored when it is only 16KB
+            vec![offset % prg.len()]
+        } else {
+            // bank switched, the code could land in any 8KB bank
+            if code.compare.is_none() {
+                return Err(format!(
+                    "{} needs a compare value on a bank switched ROM, use an 8 letter code",
+                    code.code
+                ));
+            }
+            (0..prg.len() / 0x2000)
+                .map(|bank| bank * 0x2000 + (offset & 0x1fff))
+                .collect()
+        };
+        for at in banks {
+            if code.compare.is_none_or(|compare| prg[at] == compare) {
+                prg[at] = code.value;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 16KB NROM image with every PRG byte set to 0xea
+    fn nrom() -> Vec<u8> {
+        let mut rom = b"NES\x1a\x01\x01\x00\x00".to_vec();
+        rom.resize(16, 0);
+        rom.resize(16 + 0x4000 + 0x2000, 0xea);
+        rom
+    }
+
+    #[test]
+    fn decodes_six_letter_codes() {
+        // infinite lives in super mario bros
+        let code = Code::decode("sxiopo").unwrap();
+        assert_eq!(code.code, "SXIOPO");
+        assert_eq!(code.address, 0x91d9);
+        assert_eq!(code.value, 0xad);
+        assert_eq!(code.compare, None);
+    }
+
+    #[test]
+    fn patches_mirrored_prg() {
+        let mut rom = nrom();
+        patch(&mut rom, &decode_all("SXIOPO").unwrap()).unwrap();
+        assert_eq!(rom[16 + 0x11d9], 0xad);
+        assert_eq!(rom.iter().filter(|&&byte| byte == 0xad).count(), 1);
+    }
+
+    #[test]
+    fn decodes_eight_letter_codes() {
+        let code = Code::decode("SXIOPOZE").unwrap();
+        assert_eq!(code.address, 0x91d9);
+        assert_eq!(code.value, 0xad);
+        assert_eq!(code.compare, Some(0x0a));
+    }
+
+    #[test]
+    fn patches_only_when_the_compare_byte_matches() {
+        let codes = decode_all("SXIOPOZE").unwrap();
+
+        let mut rom = nrom();
+        rom[16 + 0x11d9] = 0x0a;
+        patch(&mut rom, &codes).unwrap();
+        assert_eq!(rom[16 + 0x11d9], 0xad);
+
+        let mut rom = nrom();
+        patch(&mut rom, &codes).unwrap();
+        assert_eq!(rom[16 + 0x11d9], 0xea);
+    }
+
+    #[test]
+    fn refuses_short_roms() {
+        let codes = decode_all("SXIOPO").unwrap();
+        assert!(patch(&mut [0; 8], &codes).is_err());
+
+        let mut rom = nrom();
+        rom.truncate(16 + 0x2000);
+        assert!(patch(&mut rom, &codes).is_err());
+
+        let mut rom = nrom();
+        rom[4] = 0;
+        assert!(patch(&mut rom, &codes).is_err());
+    }
+}
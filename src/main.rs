@@ -1,291 +1,464 @@
-use discord::channel::{Channel, ChannelResource};
+mod activity;
+mod anim;
+mod clock;
+mod commands;
+mod emulator;
+mod error;
+mod fm2;
+mod frame;
+mod genie;
+mod profile;
+mod ram_watch;
+mod render;
+mod replay;
+mod rom;
+mod schedule;
+mod script;
+mod session;
+mod vote;
+
+use anim::AnimFormat;
+use clock::Region;
+use commands::{ephemeral, Command};
+use discord::channel::Channel;
 use discord::gateway::{Gateway, GatewayEvent};
-use discord::interaction::{AnyInteraction, ComponentInteractionResource, CreateUpdate, Webhook};
-use discord::message::{
-    ActionRow, ActionRowComponent, Attachment, Button, ButtonStyle, CreateAttachment, CreateMessage,
+use discord::interaction::{
+    AnyInteraction, CommandInteraction, CreateReply, InteractionResource, Webhook,
 };
-use discord::request::{Bot, File, IndexedOr, Result};
+use discord::message::MessageFlags;
+use discord::request::{Bot, Error as RequestError};
 use discord::resource::Snowflake;
+use discord::user::User;
 use dotenv::dotenv;
-use fastnes::ppu::DrawOptions;
-use fastnes::{input::Controllers, nes::NES, ppu::FastPPU};
-use image::codecs::gif::GifEncoder;
-use image::{ColorType, ImageOutputFormat};
+use error::{Error, Result};
+use profile::GameProfile;
+use render::StillFormat;
+use session::Session;
+use std::collections::HashMap;
 use std::env;
-use std::io::Cursor;
-use std::io::Write;
-use std::sync::atomic::{AtomicU8, Ordering};
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time;
 
-type Frame = [fastnes::ppu::Color; 61440];
+pub struct Config {
+    pub rom: String,
+    // name of an uploaded ROM, None for the one from ROM
+    pub game: Option<String>,
+    // the ROM with the game genie codes applied, in a temporary file
+    pub patched: Option<String>,
+    pub genie: Vec<genie::Code>,
+    pub region: Region,
+    pub profile: Box<dyn GameProfile>,
+    pub still: StillFormat,
+    pub max_edits: u32,
+    pub owner: Option<Snowflake<User>>,
+    pub vote_window: Duration,
+    pub rewind_depth: usize,
+    pub scale: u32,
+    pub anim: AnimFormat,
+    pub idle_timeout: Duration,
+    pub activity_log: usize,
+}
 
-fn encode_frame<W: Write>(gif: &mut GifEncoder<W>, nes: &mut NES) {
-    nes.next_frame();
-    let frame = nes.draw_frame(DrawOptions::All);
-    gif.encode(
-        unsafe {
-            ::core::slice::from_raw_parts(
-                (&frame as *const Frame) as *const u8,
-                ::core::mem::size_of::<Frame>(),
-            )
-        },
-        256,
-        240,
-        ColorType::Rgba8,
-    )
-    .unwrap();
+impl Config {
+    // uploaded games are played with the generic profile and no cheats
+    fn uploaded(&self, game: String, rom: PathBuf, region: Region) -> Config {
+        Config {
+            rom: rom.to_string_lossy().into_owned(),
+            game: Some(game),
+            patched: None,
+            genie: Vec::new(),
+            region,
+            profile: profile::by_name("generic").unwrap(),
+            still: self.still,
+            max_edits: self.max_edits,
+            owner: self.owner,
+            vote_window: self.vote_window,
+            rewind_depth: self.rewind_depth,
+            scale: self.scale,
+            anim: self.anim,
+            idle_timeout: self.idle_timeout,
+            activity_log: self.activity_log,
+        }
+    }
 }
 
-fn as_png(frame: &Frame, name: String) -> File {
-    let mut buffer = Cursor::new(Vec::new());
-    image::write_buffer_with_format(
-        &mut buffer,
-        unsafe {
-            ::core::slice::from_raw_parts(
-                (frame as *const Frame) as *const u8,
-                ::core::mem::size_of::<Frame>(),
-            )
-        },
-        256,
-        240,
-        ColorType::Rgba8,
-        ImageOutputFormat::Png,
-    )
-    .unwrap();
-    let data = buffer.into_inner();
+// larger frames quickly run into the upload limit
+const MAX_SCALE: u32 = 4;
 
-    File {
-        name,
-        typ: "image/png".into(),
-        data: data.into(),
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(60);
+
+// an optional environment variable that has to parse if it is set
+fn number<T: FromStr>(name: &str, default: T) -> Result<T> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| Error::Config(format!("{name} is not a number"))),
+        Err(_) => Ok(default),
     }
 }
 
-fn components(input: u8) -> Vec<ActionRow> {
-    let button = |custom_id: &str, label: Option<&str>, bit: Option<u8>| {
-        ActionRowComponent::Button(Button::Action {
-            style: if let Some(bit) = bit {
-                if input & 1 << bit == 0 {
-                    ButtonStyle::Primary
-                } else {
-                    ButtonStyle::Success
-                }
-            } else {
-                ButtonStyle::Secondary
-            },
-            custom_id: custom_id.into(),
-            disabled: label.is_none(),
-            label: Some(label.unwrap_or("_").into()),
-        })
+async fn run() -> Result<()> {
+    // load dotenv, the variables can also come from the environment itself
+    dotenv().ok();
+    let token = env::var("TOKEN").map_err(|_| Error::MissingEnv("TOKEN"))?;
+    let max_edits: u32 = number("ATTACHMENT_MAX_EDITS", 20)?;
+    let anim = match env::var("ANIM_FORMAT").as_deref() {
+        Ok("gif") | Err(_) => AnimFormat::Gif,
+        Ok("apng") => AnimFormat::Apng,
+        Ok(format) => {
+            return Err(Error::Config(format!(
+                "ANIM_FORMAT {format} is not supported, use gif or apng"
+            )))
+        }
+    };
+    let scale: u32 = number("SCALE", 2)?;
+    if !(1..=MAX_SCALE).contains(&scale) {
+        return Err(Error::Config(format!(
+            "SCALE must be between 1 and {MAX_SCALE}"
+        )));
+    }
+    let rewind_depth: usize = number("REWIND_DEPTH", 20)?;
+    let activity_log: usize = number("ACTIVITY_LOG_SIZE", 5000)?;
+    let idle_timeout = Duration::from_secs(number("IDLE_TIMEOUT_SECS", 1800)?);
+    let vote_window = Duration::from_secs(number("VOTE_WINDOW", 10)?);
+    let still = match env::var("STILL_FORMAT").as_deref() {
+        Ok("png") | Err(_) => StillFormat::Png,
+        Ok("jpeg") => StillFormat::Jpeg(number("JPEG_QUALITY", 80)?),
+        Ok("webp") => StillFormat::WebP(number("WEBP_QUALITY", 80)?),
+        Ok(format) => {
+            return Err(Error::Config(format!(
+                "STILL_FORMAT {format} is not supported, use png, jpeg or webp"
+            )))
+        }
     };
-    vec![
-        ActionRow::new(vec![
-            button("00", None, None),
-            button("up", Some("⬆"), Some(4)),
-            button("02", None, None),
-            button("03", None, None),
-            button("04", None, None),
-        ]),
-        ActionRow::new(vec![
-            button("left", Some("⬅"), Some(6)),
-            button("11", None, None),
-            button("right", Some("➡"), Some(7)),
-            button("13", None, None),
-            button("a", Some("🅰️"), Some(0)),
-        ]),
-        ActionRow::new(vec![
-            button("20", None, None),
-            button("down", Some("⬇"), Some(5)),
-            button("22", None, None),
-            button("b", Some("🅱️"), Some(1)),
-            button("24", None, None),
-        ]),
-        ActionRow::new(vec![
-            button("next", Some("Next"), None),
-            button("reset", Some("Reset"), None),
-        ]),
-    ]
-}
 
-async fn display(
-    client: &Bot,
-    nes: &mut NES,
-    input: u8,
-    channel: Snowflake<Channel>,
-) -> Result<Snowflake<Attachment>> {
-    let frame = nes.draw_frame(DrawOptions::All);
-    let img = as_png(&frame, "frame.png".into());
+    let rom = env::var("ROM").unwrap_or("rom/smb.nes".into());
+    let profile = env::var("PROFILE").unwrap_or("smb".into());
+    let profile = profile::by_name(&profile).ok_or_else(|| {
+        Error::Config(format!(
+            "PROFILE {profile} does not exist, use smb or generic"
+        ))
+    })?;
+    let mut image = fs::read(&rom).map_err(|err| Error::Rom(rom.clone(), err))?;
+    let region = rom::validate(&image).map_err(|err| Error::Config(format!("{rom}: {err}")))?;
 
-    let msg = channel
-        .send_message(
-            &client,
-            CreateMessage::default()
-                .components(components(input))
-                .attachments(vec![CreateAttachment::new(img)].into()),
-        )
-        .await?;
+    let genie = genie::decode_all(&env::var("GENIE").unwrap_or_default())
+        .map_err(|err| Error::Config(format!("GENIE has {err}")))?;
+    let patched = if genie.is_empty() {
+        None
+    } else {
+        genie::patch(&mut image, &genie).map_err(|err| Error::Config(format!("GENIE: {err}")))?;
+        // fastnes only boots from a file
+        let path = env::temp_dir().join(format!("discord-nes-{}.nes", process::id()));
+        let path = path.to_string_lossy().into_owned();
+        fs::write(&path, image).map_err(|err| Error::Rom(path.clone(), err))?;
+        Some(path)
+    };
 
-    Ok(msg.attachments[0].id)
-}
+    let config = Arc::new(Config {
+        rom,
+        game: None,
+        patched,
+        genie,
+        region,
+        profile,
+        still,
+        max_edits,
+        owner: env::var("OWNER")
+            .ok()
+            .map(|owner| {
+                owner
+                    .try_into()
+                    .map_err(|_| Error::Config("OWNER is not a valid user id".into()))
+            })
+            .transpose()?,
+        vote_window,
+        rewind_depth,
+        scale,
+        anim,
+        idle_timeout,
+        activity_log,
+    });
 
-fn can_control_mario(nes: &NES) -> bool {
-    nes.read_internal(0x000e) == 8
-}
+    // connect
+    let client = Arc::new(Bot::new(token));
+    commands::register(&client).await?;
 
-async fn run() -> Result<()> {
-    // create emulator
-    let input = Arc::new(AtomicU8::new(0));
-    let controllers = Controllers::standard(&input);
-    let mut nes = NES::read_ines("rom/smb.nes", controllers, FastPPU::new());
+    // one task per channel, each with its own emulator thread
+    let mut sessions: HashMap<Snowflake<Channel>, UnboundedSender<AnyInteraction>> = HashMap::new();
 
-    // run until 1-1
-    for _ in 0..60 {
-        nes.next_frame();
-    }
+    // sessions save and disable their controls once this is set
+    let (stop, stopped) = watch::channel(false);
+    let mut tasks: Vec<JoinHandle<()>> = Vec::new();
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
 
-    input.store(1 << 3, Ordering::Relaxed);
-    nes.next_frame();
-    input.store(0, Ordering::Relaxed);
+    // gateway, sessions keep running while it reconnects
+    let mut backoff = RECONNECT_MIN;
+    let mut stopping = false;
+    let mut rejected = false;
+    loop {
+        // a shutdown while connecting or waiting to reconnect stops right away
+        let connected = tokio::select! {
+            connected = Gateway::connect(&client) => connected,
+            _ = &mut shutdown => break,
+        };
+        let mut gateway = match connected {
+            Ok(gateway) => gateway,
+            Err(RequestError::Unauthorized) => {
+                // running sessions still get to save before exiting
+                rejected = true;
+                break;
+            }
+            Err(err) => {
+                eprintln!("could not connect to the gateway: {err}, retrying in {backoff:?}");
+                tokio::select! {
+                    _ = time::sleep(backoff) => {}
+                    _ = &mut shutdown => break,
+                }
+                backoff = (backoff * 2).min(RECONNECT_MAX);
+                continue;
+            }
+        };
 
-    for _ in 0..60 {
-        nes.next_frame();
-    }
-    while !can_control_mario(&nes) {
-        nes.next_frame();
-    }
+        loop {
+            let event = tokio::select! {
+                event = gateway.next() => event,
+                _ = &mut shutdown => {
+                    stopping = true;
+                    None
+                }
+            };
+            let Some(event) = event else {
+                break;
+            };
+            backoff = RECONNECT_MIN;
 
-    // load dotenv
-    dotenv().unwrap();
-    let token = env::var("TOKEN").expect("Bot token TOKEN must be set");
-    let channel: Snowflake<Channel> = env::var("CHANNEL")
-        .expect("CHANNEL must be set")
-        .try_into()
-        .expect("CHANNEL is not a valid channel id");
+            let GatewayEvent::InteractionCreate(interaction) = event else {
+                continue;
+            };
 
-    // connect
-    let client = Bot::new(token);
+            // forget sessions that were stopped
+            sessions.retain(|_, events| !events.is_closed());
+            tasks.retain(|task| !task.is_finished());
 
-    // channel to test in
-    let mut attachment = display(&client, &mut nes, 0, channel).await?;
+            let channel = match &interaction {
+                AnyInteraction::Command(i) => i.channel_id,
+                AnyInteraction::Component(i) => i.channel_id,
+                _ => continue,
+            };
 
-    // gateway
-    let mut gateway = Gateway::connect(&client).await?;
-    while let Some(event) = gateway.next().await {
-        match event {
-            GatewayEvent::InteractionCreate(AnyInteraction::Component(i)) => {
-                // flip input
-                let mut byte = input.load(Ordering::Relaxed);
-                byte ^= 1
-                    << match i.data.custom_id.as_str() {
-                        "a" => 0,
-                        "b" => 1,
-                        "up" => 4,
-                        "down" => 5,
-                        "left" => 6,
-                        "right" => 7,
-                        "next" => {
-                            let mut bytes = Vec::new();
-                            let mut gif = GifEncoder::new_with_speed(&mut bytes, 30);
+            match sessions.get(&channel) {
+                Some(events) => {
+                    let _ = events.send(interaction);
+                }
+                None => match interaction {
+                    AnyInteraction::Command(i)
+                        if commands::parse(&i.data) == Some(Command::Games(None)) =>
+                    {
+                        let server = i
+                            .guild_id
+                            .map_or(channel.to_string(), |guild| guild.to_string());
+                        let reply = ephemeral(rom::games(&server));
+                        if let Err(err) = i.reply(&Webhook, reply).await {
+                            eprintln!("could not reply in {channel}: {err}");
+                        }
+                    }
+                    AnyInteraction::Command(i)
+                        if matches!(
+                            commands::parse(&i.data),
+                            Some(Command::Start | Command::Load { .. } | Command::Games(Some(_)))
+                        ) =>
+                    {
+                        let (events, receiver) = mpsc::unbounded_channel();
+                        sessions.insert(channel, events);
 
-                            // run emu for 10 frames
-                            for _ in 0..5 {
-                                // the GIF encoder cannot succeed 30fps while the game runs at 60
-                                // so we only show half the frames
-                                nes.next_frame();
-                                encode_frame(&mut gif, &mut nes);
-                            }
-                            while !can_control_mario(&nes) {
-                                nes.next_frame();
-                                encode_frame(&mut gif, &mut nes);
+                        let client = client.clone();
+                        let config = config.clone();
+                        let stopped = stopped.clone();
+                        tasks.push(tokio::spawn(async move {
+                            let command = commands::parse(&i.data);
+                            // downloads and booting take longer than discord waits for a reply,
+                            // only cheats on the configured ROM are announced publicly
+                            let flags = match command {
+                                Some(Command::Start) if !config.genie.is_empty() => {
+                                    MessageFlags::empty()
+                                }
+                                _ => MessageFlags::EPHEMERAL,
+                            };
+                            if let Err(err) = i.defer(&Webhook, flags).await {
+                                eprintln!("could not reply in {channel}: {err}");
+                                return;
                             }
-                            drop(gif);
 
-                            // display
-                            let img = File {
-                                name: "frames.gif".into(),
-                                typ: "image/gif".into(),
-                                data: bytes.into(),
+                            let config = match command {
+                                Some(Command::Start) => config,
+                                command => match game(&config, &i, command).await {
+                                    Ok(config) => Arc::new(config),
+                                    Err(err) => {
+                                        if let Err(err) = i.followup(&Webhook, ephemeral(err)).await
+                                        {
+                                            eprintln!("could not reply in {channel}: {err}");
+                                        }
+                                        return;
+                                    }
+                                },
                             };
-
-                            let msg = i
-                                .update(
-                                    &Webhook,
-                                    CreateUpdate::default()
-                                        .components(components(byte))
-                                        .attachments(IndexedOr(
-                                            vec![CreateAttachment::new(img)],
-                                            vec![],
-                                        )),
-                                )
-                                .await?
-                                .get(&Webhook)
-                                .await?;
-
-                            attachment = msg.attachments[0].id;
-                            continue;
+                            play(client, config, i, receiver, stopped).await;
+                        }));
+                    }
+                    AnyInteraction::Command(i) => {
+                        let reply = ephemeral("No game is running in this channel");
+                        if let Err(err) = i.reply(&Webhook, reply).await {
+                            eprintln!("could not reply in {channel}: {err}");
                         }
-                        "reset" => {
-                            nes.reset();
-                            input.store(0, Ordering::Relaxed);
+                    }
+                    AnyInteraction::Component(i) => {
+                        let reply = ephemeral("This game has ended, use /nes start to play again");
+                        if let Err(err) = i.reply(&Webhook, reply).await {
+                            eprintln!("could not reply in {channel}: {err}");
+                        }
+                    }
+                    _ => {}
+                },
+            }
+        }
+        gateway.close().await;
+        if stopping {
+            break;
+        }
 
-                            // run until 1-1
-                            for _ in 0..60 {
-                                nes.next_frame();
-                            }
+        eprintln!("gateway disconnected, reconnecting in {backoff:?}");
+        tokio::select! {
+            _ = time::sleep(backoff) => {}
+            _ = &mut shutdown => break,
+        }
+        backoff = (backoff * 2).min(RECONNECT_MAX);
+    }
 
-                            input.store(1 << 3, Ordering::Relaxed);
-                            nes.next_frame();
-                            input.store(0, Ordering::Relaxed);
+    // let every session save before exiting
+    let _ = stop.send(true);
+    for task in tasks {
+        let _ = task.await;
+    }
+    if rejected {
+        return Err(Error::Config("TOKEN was rejected by discord".into()));
+    }
+    Ok(())
+}
 
-                            for _ in 0..60 {
-                                nes.next_frame();
-                            }
-                            while !can_control_mario(&nes) {
-                                nes.next_frame();
-                            }
+// downloads or looks up an uploaded ROM
+async fn game(
+    config: &Config,
+    i: &CommandInteraction,
+    command: Option<Command>,
+) -> std::result::Result<Config, String> {
+    let server = i
+        .guild_id
+        .map_or(i.channel_id.to_string(), |guild| guild.to_string());
+    match command {
+        Some(Command::Load {
+            filename,
+            url,
+            size,
+        }) => {
+            if size > rom::MAX_SIZE {
+                return Err(format!(
+                    "ROMs can be at most {} MB",
+                    rom::MAX_SIZE / 1024 / 1024
+                ));
+            }
+            let image = rom::download(&url).await?;
+            let region = rom::validate(&image)?;
+            let name = rom::name(&filename);
+            let path = rom::store(&server, &name, &image)?;
+            Ok(config.uploaded(name, path, region))
+        }
+        Some(Command::Games(Some(name))) => {
+            let name = rom::name(&name);
+            let path = rom::path(&server, &name);
+            let image = fs::read(&path)
+                .map_err(|_| format!("{name} was never uploaded, use /nes games to list them"))?;
+            let region = rom::validate(&image)?;
+            Ok(config.uploaded(name, path, region))
+        }
+        _ => unreachable!(),
+    }
+}
 
-                            // display
-                            let frame = nes.draw_frame(DrawOptions::All);
-                            let img = as_png(&frame, "frame.png".into());
+async fn play(
+    client: Arc<Bot>,
+    config: Arc<Config>,
+    i: CommandInteraction,
+    receiver: UnboundedReceiver<AnyInteraction>,
+    stopped: watch::Receiver<bool>,
+) {
+    let channel = i.channel_id;
+    let session = match Session::start(client, config.clone(), channel, i.user.id).await {
+        Ok(session) => session,
+        Err(err) => {
+            eprintln!("could not start a game in {channel}: {err}");
+            let reply = ephemeral(format!("Could not start the game: {err}"));
+            if let Err(err) = i.followup(&Webhook, reply).await {
+                eprintln!("could not reply in {channel}: {err}");
+            }
+            return;
+        }
+    };
 
-                            let msg = i
-                                .update(
-                                    &Webhook,
-                                    CreateUpdate::default()
-                                        .components(components(byte))
-                                        .attachments(IndexedOr(
-                                            vec![CreateAttachment::new(img)],
-                                            vec![],
-                                        )),
-                                )
-                                .await?
-                                .get(&Webhook)
-                                .await?;
+    let mut content = match &config.game {
+        Some(game) => format!("Started {game}"),
+        None => "Game started".to_string(),
+    };
+    if !config.genie.is_empty() {
+        let codes: Vec<_> = config.genie.iter().map(|code| code.code.as_str()).collect();
+        content += &format!(" with Game Genie codes {}", codes.join(", "));
+    }
+    if session.has_autosave() {
+        content += ", the bot went offline during the last game";
+    } else if session.has_save() {
+        content += ", press Load to resume the saved game";
+    }
 
-                            attachment = msg.attachments[0].id;
-                            continue;
-                        }
-                        _ => continue,
-                    };
-                input.store(byte, Ordering::Relaxed);
+    // cheats are announced so everyone knows the run is modified, the deferral picked the flags
+    let mut reply = CreateReply::default().content(content);
+    if session.has_autosave() {
+        reply = reply.components(session::autosave_controls());
+    }
+    if let Err(err) = i.followup(&Webhook, reply).await {
+        eprintln!("could not reply in {channel}: {err}");
+    }
+    session.run(receiver, stopped).await;
+}
 
-                // display
-                i.update(
-                    &Webhook,
-                    CreateUpdate::default()
-                        .components(components(byte))
-                        .attachments(IndexedOr(vec![], vec![attachment.into()])),
-                )
-                .await?;
-            }
-            _ => {}
+// ctrl-c, or SIGTERM on unix
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate()).unwrap();
+        tokio::select! {
+            _ = signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
         }
     }
-    gateway.close().await;
-    Ok(())
+    #[cfg(not(unix))]
+    signal::ctrl_c().await.unwrap();
 }
 
 #[tokio::main]
 async fn main() {
-    run().await.unwrap()
+    if let Err(err) = run().await {
+        eprintln!("{err}");
+        process::exit(1);
+    }
 }
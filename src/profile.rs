@@ -0,0 +1,51 @@
+use crate::ram_watch::Watch;
+use crate::replay::InputLog;
+use fastnes::nes::NES;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+mod smb;
+
+pub trait GameProfile: Send + Sync {
+    // bring a freshly reset emulator to the point where players take over,
+    // stepping through the log so the boot presses end up in exported movies
+    fn boot(&self, nes: &mut NES, input: &AtomicU8, log: &mut InputLog);
+
+    // advances keep running until this returns true
+    fn is_input_ready(&self, nes: &NES) -> bool;
+
+    // RAM values shown next to the frame
+    fn watches(&self) -> &[Watch] {
+        &[]
+    }
+
+    // buttons that are released after every advance, select and start open most menus
+    fn momentary(&self) -> u8 {
+        1 << 2 | 1 << 3
+    }
+}
+
+// works with any ROM, just lets it run for a bit
+pub struct Generic {
+    pub boot_frames: u32,
+}
+
+impl GameProfile for Generic {
+    fn boot(&self, nes: &mut NES, input: &AtomicU8, log: &mut InputLog) {
+        input.store(0, Ordering::Relaxed);
+        for _ in 0..self.boot_frames {
+            log.step(nes, input);
+        }
+    }
+
+    fn is_input_ready(&self, _nes: &NES) -> bool {
+        true
+    }
+}
+
+pub fn by_name(name: &str) -> Option<Box<dyn GameProfile>> {
+    match name {
+        "smb" => Some(Box::new(smb::Smb)),
+        "generic" => Some(Box::new(Generic { boot_frames: 60 })),
+        _ => None,
+    }
+}
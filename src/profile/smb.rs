@@ -0,0 +1,71 @@
+use super::GameProfile;
+use crate::ram_watch::{Decode, Watch};
+use crate::replay::InputLog;
+use fastnes::nes::NES;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+pub struct Smb;
+
+const WATCHES: [Watch; 5] = [
+    Watch {
+        name: "Lives",
+        address: 0x075a,
+        decode: Decode::OneBased,
+    },
+    Watch {
+        name: "World",
+        address: 0x075f,
+        decode: Decode::OneBased,
+    },
+    Watch {
+        name: "Level",
+        address: 0x0760,
+        decode: Decode::OneBased,
+    },
+    Watch {
+        name: "Score",
+        address: 0x07dd,
+        decode: Decode::Digits(6),
+    },
+    Watch {
+        name: "Time",
+        address: 0x07f8,
+        decode: Decode::Digits(3),
+    },
+];
+
+impl GameProfile for Smb {
+    fn boot(&self, nes: &mut NES, input: &AtomicU8, log: &mut InputLog) {
+        input.store(0, Ordering::Relaxed);
+
+        // run until 1-1
+        for _ in 0..60 {
+            log.step(nes, input);
+        }
+
+        input.store(1 << 3, Ordering::Relaxed);
+        log.step(nes, input);
+        input.store(0, Ordering::Relaxed);
+
+        for _ in 0..60 {
+            log.step(nes, input);
+        }
+        while !self.is_input_ready(nes) {
+            log.step(nes, input);
+        }
+    }
+
+    // mario can be controlled
+    fn is_input_ready(&self, nes: &NES) -> bool {
+        nes.read_internal(0x000e) == 8
+    }
+
+    fn watches(&self) -> &[Watch] {
+        &WATCHES
+    }
+
+    // jumps and fireballs are taps too: a, b, select, start
+    fn momentary(&self) -> u8 {
+        0b0000_1111
+    }
+}
@@ -0,0 +1,39 @@
+use fastnes::nes::NES;
+
+pub enum Decode {
+    // zero based counters, shown the way the game shows them
+    OneBased,
+    // one decimal digit per byte, most significant first
+    Digits(u16),
+}
+
+pub struct Watch {
+    pub name: &'static str,
+    pub address: u16,
+    pub decode: Decode,
+}
+
+impl Watch {
+    fn read(&self, nes: &NES) -> String {
+        match self.decode {
+            Decode::OneBased => (nes.read_internal(self.address) as u32 + 1).to_string(),
+            Decode::Digits(len) => (0..len)
+                .map(|i| {
+                    let digit = nes.read_internal(self.address + i) as u32 % 10;
+                    char::from_digit(digit, 10).unwrap()
+                })
+                .collect(),
+        }
+    }
+}
+
+pub fn render(watches: &[Watch], nes: &NES) -> Option<String> {
+    if watches.is_empty() {
+        return None;
+    }
+    let values: Vec<_> = watches
+        .iter()
+        .map(|watch| format!("**{}** {}", watch.name, watch.read(nes)))
+        .collect();
+    Some(values.join(" · "))
+}
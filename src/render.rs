@@ -0,0 +1,173 @@
+use crate::error::Result;
+use crate::frame::{frame_to_rgba, upscale, Frame, HEIGHT, WIDTH};
+use discord::request::File;
+use fastnes::ppu::DrawOptions;
+use image::{ColorType, ImageOutputFormat};
+use std::io::Cursor;
+
+// layers that can be picked from the select menu: value, label, options
+pub const LAYERS: [(&str, &str, DrawOptions); 3] = [
+    ("all", "All", DrawOptions::All),
+    ("background", "Background only", DrawOptions::Background),
+    ("sprites", "Sprites only", DrawOptions::Sprites),
+];
+
+fn as_png(frame: &Frame, name: String, scale: u32) -> Result<File> {
+    let mut buffer = Cursor::new(Vec::new());
+    image::write_buffer_with_format(
+        &mut buffer,
+        &upscale(&frame_to_rgba(frame, None), scale),
+        WIDTH * scale,
+        HEIGHT * scale,
+        ColorType::Rgba8,
+        ImageOutputFormat::Png,
+    )?;
+    let data = buffer.into_inner();
+
+    Ok(File {
+        name,
+        typ: "image/png".into(),
+        data: data.into(),
+    })
+}
+
+fn as_jpeg(frame: &Frame, name: String, quality: u8, scale: u32) -> Result<File> {
+    // jpeg has no alpha channel
+    let rgb: Vec<u8> = upscale(&frame_to_rgba(frame, None), scale)
+        .chunks_exact(4)
+        .flat_map(|pixel| &pixel[..3])
+        .copied()
+        .collect();
+
+    let mut buffer = Cursor::new(Vec::new());
+    image::write_buffer_with_format(
+        &mut buffer,
+        &rgb,
+        WIDTH * scale,
+        HEIGHT * scale,
+        ColorType::Rgb8,
+        ImageOutputFormat::Jpeg(quality),
+    )?;
+    let data = buffer.into_inner();
+
+    Ok(File {
+        name,
+        typ: "image/jpeg".into(),
+        data: data.into(),
+    })
+}
+
+fn as_webp(frame: &Frame, name: String, quality: u8, scale: u32) -> Result<File> {
+    let rgba = upscale(&frame_to_rgba(frame, None), scale).into_owned();
+    let data = webp::Encoder::from_rgba(&rgba, WIDTH * scale, HEIGHT * scale)
+        .encode(quality as f32)
+        .to_vec();
+
+    Ok(File {
+        name,
+        typ: "image/webp".into(),
+        data: data.into(),
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StillFormat {
+    Png,
+    Jpeg(u8),
+    WebP(u8),
+}
+
+pub fn as_still(frame: &Frame, format: StillFormat, scale: u32) -> Result<File> {
+    match format {
+        StillFormat::Png => as_png(frame, "frame.png".into(), scale),
+        StillFormat::Jpeg(quality) => as_jpeg(frame, "frame.jpg".into(), quality, scale),
+        StillFormat::WebP(quality) => as_webp(frame, "frame.webp".into(), quality, scale),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fastnes::ppu::Color;
+
+    const PALETTE: [(u8, u8, u8); 8] = [
+        (0, 0, 0),
+        (252, 252, 252),
+        (248, 56, 0),
+        (0, 120, 248),
+        (0, 168, 0),
+        (252, 160, 68),
+        (104, 136, 252),
+        (136, 20, 0),
+    ];
+
+    // 8x8 tiles of NES colours with a diagonal stroke, busier than most real frames
+    fn reference() -> Box<Frame> {
+        let black = Color { r: 0, g: 0, b: 0 };
+        let mut frame = Box::new([black; (WIDTH * HEIGHT) as usize]);
+        for (i, color) in frame.iter_mut().enumerate() {
+            let (x, y) = (i % WIDTH as usize, i / WIDTH as usize);
+            let tile = (x / 8 * 7 + y / 8 * 13) % PALETTE.len();
+            let tile = if x % 8 == y % 8 {
+                (tile + 1) % PALETTE.len()
+            } else {
+                tile
+            };
+            let (r, g, b) = PALETTE[tile];
+            *color = Color { r, g, b };
+        }
+        frame
+    }
+
+    // peak signal to noise ratio of the decoded image against the source, in dB
+    fn psnr(frame: &Frame, rgb: &[u8], scale: u32) -> f64 {
+        let source = upscale(&frame_to_rgba(frame, None), scale).into_owned();
+        let mut error = 0.0;
+        for (source, decoded) in source.chunks_exact(4).zip(rgb.chunks_exact(3)) {
+            for channel in 0..3 {
+                let diff = source[channel] as f64 - decoded[channel] as f64;
+                error += diff * diff;
+            }
+        }
+        let mse = error / (rgb.len() as f64);
+        10.0 * (255.0 * 255.0 / mse).log10()
+    }
+
+    #[test]
+    fn jpeg_size_and_quality() {
+        let frame = reference();
+        let file = as_still(&frame, StillFormat::Jpeg(80), 2).unwrap();
+        assert_eq!(file.typ, "image/jpeg");
+        assert!(file.data.len() < 150 * 1024, "{} bytes", file.data.len());
+
+        let decoded = image::load_from_memory(&file.data).unwrap().to_rgb8();
+        assert_eq!(decoded.dimensions(), (WIDTH * 2, HEIGHT * 2));
+        let psnr = psnr(&frame, decoded.as_raw(), 2);
+        assert!(psnr > 25.0, "{psnr} dB");
+    }
+
+    #[test]
+    fn webp_size_and_quality() {
+        let frame = reference();
+        let file = as_still(&frame, StillFormat::WebP(80), 2).unwrap();
+        assert_eq!(file.typ, "image/webp");
+        assert!(file.data.len() < 100 * 1024, "{} bytes", file.data.len());
+
+        let decoded = webp::Decoder::new(&file.data).decode().unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (WIDTH * 2, HEIGHT * 2));
+        let rgb = decoded.to_image().to_rgb8();
+        let psnr = psnr(&frame, rgb.as_raw(), 2);
+        assert!(psnr > 25.0, "{psnr} dB");
+    }
+
+    #[test]
+    fn lossy_stills_are_smaller_than_png() {
+        let frame = reference();
+        let png = as_still(&frame, StillFormat::Png, 2).unwrap().data.len();
+        let webp = as_still(&frame, StillFormat::WebP(80), 2)
+            .unwrap()
+            .data
+            .len();
+        assert!(webp < png);
+    }
+}
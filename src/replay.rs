@@ -0,0 +1,330 @@
+use fastnes::nes::NES;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// adding a save format version:
+// 1. append an upgrade from the previous version to UPGRADES and change encode/decode
+// 2. run `cargo test -- --ignored write_fixture` and add the new fixture to FIXTURES in the tests
+// older fixtures are never regenerated, they are what players' saves look like
+
+// turns a save of version n + 1 into one of version n + 2
+type Upgrade = fn(Vec<u8>) -> io::Result<Vec<u8>>;
+
+const UPGRADES: &[Upgrade] = &[v1_to_v2];
+
+const VERSION: u8 = UPGRADES.len() as u8 + 1;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+// v2 counts the input changes so pokes can follow them
+fn v1_to_v2(mut data: Vec<u8>) -> io::Result<Vec<u8>> {
+    if data.len() < 10 || !(data.len() - 10).is_multiple_of(9) {
+        return Err(invalid("save is truncated"));
+    }
+    let changes = (data.len() as u64 - 10) / 9;
+    data.splice(10..10, changes.to_le_bytes());
+    Ok(data)
+}
+
+// pokes only ever touch internal RAM, anything else has side effects a replay can't repeat
+pub const INTERNAL_RAM: u16 = 0x0800;
+
+// addresses are checked by /nes poke and when loading, this never fails
+fn write(nes: &mut NES, address: u16, value: u8) -> u8 {
+    let address = address % INTERNAL_RAM;
+    let old = nes.read_internal(address);
+    nes.write_internal(address, value);
+    old
+}
+
+// every input change and poke since the game was booted
+#[derive(Default)]
+pub struct InputLog {
+    frames: u64,
+    changes: Vec<(u64, u8)>,
+    // written before the frame they were made at
+    pokes: Vec<(u64, u16, u8)>,
+}
+
+impl InputLog {
+    fn record(&mut self, byte: u8) {
+        if self.changes.last().map(|&(_, last)| last) != Some(byte) {
+            self.changes.push((self.frames, byte));
+        }
+        self.frames += 1;
+    }
+
+    // runs a single frame with the current input
+    pub fn step(&mut self, nes: &mut NES, input: &AtomicU8) {
+        self.record(input.load(Ordering::Relaxed));
+        nes.next_frame();
+    }
+
+    // writes a byte before the next frame, returning the old one
+    pub fn poke(&mut self, nes: &mut NES, address: u16, value: u8) -> u8 {
+        self.pokes.push((self.frames, address, value));
+        write(nes, address, value)
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn truncate(&mut self, frames: u64) {
+        self.changes.retain(|&(at, _)| at < frames);
+        self.pokes.retain(|&(at, ..)| at <= frames);
+        self.frames = self.frames.min(frames);
+    }
+
+    // the input of every frame in order
+    pub fn inputs(&self) -> impl Iterator<Item = u8> + '_ {
+        let mut changes = self.changes.iter().peekable();
+        let mut byte = 0;
+        (0..self.frames).map(move |frame| {
+            if let Some(&(_, change)) = changes.next_if(|&&(at, _)| at == frame) {
+                byte = change;
+            }
+            byte
+        })
+    }
+
+    // runs a freshly booted emulator up to the end of the log
+    pub fn replay(&self, nes: &mut NES, input: &AtomicU8) {
+        let mut pokes = self.pokes.iter().peekable();
+        for (frame, byte) in (0..).zip(self.inputs()) {
+            while let Some(&(_, address, value)) = pokes.next_if(|&&(at, ..)| at == frame) {
+                write(nes, address, value);
+            }
+            input.store(byte, Ordering::Relaxed);
+            nes.next_frame();
+        }
+        // pokes made after the last frame
+        for &(_, address, value) in pokes {
+            write(nes, address, value);
+        }
+    }
+
+    fn encode(&self, held: u8) -> Vec<u8> {
+        let mut data = vec![VERSION, held];
+        data.extend(self.frames.to_le_bytes());
+        data.extend((self.changes.len() as u64).to_le_bytes());
+        for &(frame, byte) in &self.changes {
+            data.extend(frame.to_le_bytes());
+            data.push(byte);
+        }
+        for &(frame, address, value) in &self.pokes {
+            data.extend(frame.to_le_bytes());
+            data.extend(address.to_le_bytes());
+            data.push(value);
+        }
+        data
+    }
+
+    fn decode(data: Vec<u8>) -> io::Result<(InputLog, u8)> {
+        let mut data = match data.first() {
+            Some(&version) if version > VERSION => {
+                return Err(invalid("save was made by a newer version of the bot"))
+            }
+            Some(0) => return Err(invalid("save was made by an unsupported version")),
+            Some(&version) => {
+                // older formats are upgraded one version at a time
+                let mut data = data;
+                for upgrade in &UPGRADES[version as usize - 1..] {
+                    data = upgrade(data)?;
+                }
+                data
+            }
+            None => return Err(invalid("save is empty")),
+        };
+        data[0] = VERSION;
+        if data.len() < 18 {
+            return Err(invalid("save is truncated"));
+        }
+
+        let held = data[1];
+        let frames = u64::from_le_bytes(data[2..10].try_into().unwrap());
+        let count = u64::from_le_bytes(data[10..18].try_into().unwrap());
+        let end = count
+            .checked_mul(9)
+            .and_then(|len| usize::try_from(len).ok())
+            .and_then(|len| len.checked_add(18))
+            .filter(|&end| end <= data.len() && (data.len() - end).is_multiple_of(11))
+            .ok_or_else(|| invalid("save is truncated"))?;
+
+        let changes = data[18..end]
+            .chunks_exact(9)
+            .map(|chunk| {
+                let frame = u64::from_le_bytes(chunk[..8].try_into().unwrap());
+                (frame, chunk[8])
+            })
+            .collect();
+        let pokes = data[end..]
+            .chunks_exact(11)
+            .map(|chunk| {
+                let frame = u64::from_le_bytes(chunk[..8].try_into().unwrap());
+                let address = u16::from_le_bytes(chunk[8..10].try_into().unwrap());
+                (frame, address, chunk[10])
+            })
+            .collect::<Vec<_>>();
+        if pokes.iter().any(|&(_, address, _)| address >= INTERNAL_RAM) {
+            return Err(invalid("save pokes outside internal RAM"));
+        }
+
+        Ok((
+            InputLog {
+                frames,
+                changes,
+                pokes,
+            },
+            held,
+        ))
+    }
+
+    pub fn save(&self, path: &Path, held: u8) -> io::Result<()> {
+        // write to a temporary file first so a crash never leaves a torn save
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, self.encode(held))?;
+        fs::rename(tmp, path)
+    }
+
+    pub fn load(path: &Path) -> io::Result<(InputLog, u8)> {
+        InputLog::decode(fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+impl InputLog {
+    // a log with one input per frame
+    pub fn from_inputs(inputs: &[u8]) -> InputLog {
+        let mut log = InputLog::default();
+        for &byte in inputs {
+            log.record(byte);
+        }
+        log
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every save format that was ever released
+    const FIXTURES: &[(u8, &[u8])] = &[
+        (1, include_bytes!("../tests/fixtures/v1.sav")),
+        (2, include_bytes!("../tests/fixtures/v2.sav")),
+    ];
+
+    // the run every fixture was made from: right for a while, a jump, then nothing
+    // with the lives set to 9 at the first jump once saves could hold pokes
+    fn run() -> (InputLog, u8) {
+        let mut log = InputLog::default();
+        let inputs = [(10, 0x00), (30, 0x80), (5, 0x81), (55, 0x80), (20, 0x00)];
+        for (frames, byte) in inputs {
+            if log.frames == 40 {
+                log.pokes.push((log.frames, 0x075a, 0x09));
+            }
+            for _ in 0..frames {
+                log.record(byte);
+            }
+        }
+        (log, 0x80)
+    }
+
+    fn assert_same(a: &InputLog, b: &InputLog) {
+        assert_eq!(a.frames(), b.frames());
+        assert!(a.inputs().eq(b.inputs()));
+        assert_eq!(a.pokes, b.pokes);
+    }
+
+    #[test]
+    fn round_trip() {
+        let (log, held) = run();
+        let (loaded, loaded_held) = InputLog::decode(log.encode(held)).unwrap();
+        assert_same(&log, &loaded);
+        assert_eq!(held, loaded_held);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let (log, held) = run();
+        let path = std::env::temp_dir().join(format!("discord-nes-{}.sav", std::process::id()));
+        log.save(&path, held).unwrap();
+        let (loaded, loaded_held) = InputLog::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_same(&log, &loaded);
+        assert_eq!(held, loaded_held);
+    }
+
+    #[test]
+    fn loads_every_fixture() {
+        assert_eq!(FIXTURES.len(), VERSION as usize);
+        let (log, held) = run();
+        for &(version, data) in FIXTURES {
+            assert_eq!(data[0], version);
+            let (loaded, loaded_held) = InputLog::decode(data.to_vec())
+                .unwrap_or_else(|err| panic!("fixture v{version}: {err}"));
+            assert_eq!(log.frames(), loaded.frames());
+            assert!(log.inputs().eq(loaded.inputs()));
+            // v1 could not hold pokes
+            if version >= 2 {
+                assert_eq!(log.pokes, loaded.pokes);
+            } else {
+                assert!(loaded.pokes.is_empty());
+            }
+            assert_eq!(held, loaded_held);
+        }
+    }
+
+    #[test]
+    fn truncate_keeps_pokes_up_to_the_frame() {
+        let (mut log, _) = run();
+        log.truncate(40);
+        assert_eq!(log.pokes, [(40, 0x075a, 0x09)]);
+        log.truncate(39);
+        assert!(log.pokes.is_empty());
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        let (log, held) = run();
+        let mut data = log.encode(held);
+        data[0] = VERSION + 1;
+        let err = InputLog::decode(data).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "save was made by a newer version of the bot"
+        );
+    }
+
+    #[test]
+    fn refuses_pokes_outside_internal_ram() {
+        let (mut log, held) = run();
+        log.pokes.push((120, 0x2000, 0x00));
+        let err = InputLog::decode(log.encode(held)).err().unwrap();
+        assert_eq!(err.to_string(), "save pokes outside internal RAM");
+    }
+
+    #[test]
+    fn refuses_truncated_saves() {
+        let (log, held) = run();
+        let mut data = log.encode(held);
+        data.pop();
+        assert!(InputLog::decode(data).is_err());
+        assert!(InputLog::decode(Vec::new()).is_err());
+    }
+
+    #[test]
+    #[ignore]
+    fn write_fixture() {
+        let (log, held) = run();
+        let path = format!("tests/fixtures/v{VERSION}.sav");
+        fs::write(path, log.encode(held)).unwrap();
+    }
+}
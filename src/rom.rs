@@ -0,0 +1,214 @@
+use crate::clock::Region;
+use futures_util::AsyncReadExt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// anything bigger is not an NES game
+pub const MAX_SIZE: u64 = 4 * 1024 * 1024;
+
+// mappers fastnes implements
+const MAPPERS: [u8; 1] = [0];
+
+// checks the iNES header of an uploaded ROM
+pub fn validate(rom: &[u8]) -> Result<Region, String> {
+    if rom.len() < 16 || &rom[0..4] != b"NES\x1a" {
+        return Err("this is not an iNES ROM".into());
+    }
+    let mapper = rom[6] >> 4 | rom[7] & 0xf0;
+    if !MAPPERS.contains(&mapper) {
+        let supported: Vec<String> = MAPPERS.iter().map(u8::to_string).collect();
+        return Err(format!(
+            "mapper {mapper} is not supported, only mapper {} works",
+            supported.join(", ")
+        ));
+    }
+    match Region::from_ines(rom) {
+        // fastnes only emulates NTSC timing
+        Region::Pal => Err("PAL ROMs are not supported yet".into()),
+        region => Ok(region),
+    }
+}
+
+// reads the attachment in chunks so a lying size never fills memory
+pub async fn download(url: &str) -> Result<Vec<u8>, String> {
+    let response = isahc::get_async(url)
+        .await
+        .map_err(|err| format!("could not download the ROM: {err}"))?;
+    if !response.status().is_success() {
+        return Err(format!("could not download the ROM: {}", response.status()));
+    }
+
+    let mut body = response.into_body();
+    let mut rom = Vec::new();
+    let mut chunk = [0; 16 * 1024];
+    loop {
+        let read = body
+            .read(&mut chunk)
+            .await
+            .map_err(|err| format!("could not download the ROM: {err}"))?;
+        if read == 0 {
+            return Ok(rom);
+        }
+        rom.extend_from_slice(&chunk[..read]);
+        if rom.len() as u64 > MAX_SIZE {
+            return Err(format!("ROMs can be at most {} MB", MAX_SIZE / 1024 / 1024));
+        }
+    }
+}
+
+// every server gets its own games
+pub fn dir(server: &str) -> PathBuf {
+    format!("roms/{server}").into()
+}
+
+// the file name without extension, restricted so it can't leave the directory
+pub fn name(filename: &str) -> String {
+    let stem = Path::new(filename)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let name: String = stem
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    if name.is_empty() {
+        "rom".into()
+    } else {
+        name
+    }
+}
+
+pub fn path(server: &str, name: &str) -> PathBuf {
+    dir(server).join(format!("{name}.nes"))
+}
+
+// a name is never reused for a different game, uploading the same file again is fine
+pub fn store(server: &str, name: &str, rom: &[u8]) -> Result<PathBuf, String> {
+    let path = path(server, name);
+    match fs::read(&path) {
+        Ok(existing) if existing == rom => return Ok(path),
+        Ok(_) => {
+            return Err(format!(
+                "another ROM was already uploaded as {name}, rename the file and try again"
+            ))
+        }
+        Err(_) => {}
+    }
+    fs::create_dir_all(dir(server))
+        .and_then(|()| fs::write(&path, rom))
+        .map_err(|err| format!("could not store the ROM: {err}"))?;
+    Ok(path)
+}
+
+// names of previously uploaded games, sorted
+pub fn list(server: &str) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir(server)) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "nes" {
+                return None;
+            }
+            Some(path.file_stem()?.to_string_lossy().into_owned())
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+pub fn games(server: &str) -> String {
+    let names = list(server);
+    if names.is_empty() {
+        "No ROMs were uploaded yet, use /nes load to add one".into()
+    } else {
+        format!("Uploaded games: {}", names.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(mapper: u8, flags9: u8) -> Vec<u8> {
+        let mut rom = b"NES\x1a\x01\x01".to_vec();
+        rom.resize(16, 0);
+        rom[6] = mapper << 4;
+        rom[7] = mapper & 0xf0;
+        rom[9] = flags9;
+        rom
+    }
+
+    #[test]
+    fn validates_nrom() {
+        assert_eq!(validate(&header(0, 0)), Ok(Region::Ntsc));
+    }
+
+    #[test]
+    fn refuses_other_files() {
+        let err = Err("this is not an iNES ROM".to_string());
+        assert_eq!(validate(b""), err);
+        assert_eq!(validate(b"NES\x1a"), err);
+        assert_eq!(validate(&[0; 16]), err);
+    }
+
+    #[test]
+    fn refuses_unsupported_mappers() {
+        assert_eq!(
+            validate(&header(1, 0)),
+            Err("mapper 1 is not supported, only mapper 0 works".into())
+        );
+        // the high nibble comes from flags 7
+        assert_eq!(
+            validate(&header(0x42, 0)),
+            Err("mapper 66 is not supported, only mapper 0 works".into())
+        );
+    }
+
+    #[test]
+    fn refuses_pal() {
+        assert_eq!(
+            validate(&header(0, 1)),
+            Err("PAL ROMs are not supported yet".into())
+        );
+    }
+
+    #[test]
+    fn names_stay_in_the_directory() {
+        assert_eq!(name("Super Mario Bros.nes"), "Super_Mario_Bros");
+        assert_eq!(name("../../etc/passwd"), "passwd");
+        assert_eq!(name("..\\..\\secret.nes"), "______secret");
+        assert_eq!(name("/abs/path/game.nes"), "game");
+        assert_eq!(name(".."), "rom");
+        assert_eq!(name(""), "rom");
+        assert_eq!(name("mario~1.nes"), "mario_1");
+        assert_eq!(
+            path("1", &name("../../x.nes")),
+            PathBuf::from("roms/1/x.nes")
+        );
+    }
+
+    #[test]
+    fn store_refuses_other_uploads() {
+        let server = format!("test-{}", std::process::id());
+        let first = header(0, 0);
+        let mut second = header(0, 0);
+        second.push(0xea);
+
+        let path = store(&server, "game", &first).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), first);
+        // the same file again is fine
+        assert_eq!(store(&server, "game", &first), Ok(path.clone()));
+        assert!(store(&server, "game", &second).is_err());
+        assert_eq!(fs::read(&path).unwrap(), first);
+        assert_eq!(list(&server), ["game"]);
+
+        fs::remove_dir_all(dir(&server)).unwrap();
+        // only goes if no real uploads live there
+        let _ = fs::remove_dir("roms");
+    }
+}
@@ -0,0 +1,84 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// press lengths the hold button cycles through, None keeps buttons held until toggled
+pub const HOLDS: [Option<u64>; 5] = [None, Some(5), Some(15), Some(30), Some(60)];
+
+// buttons that are released a number of frames into the next advance
+#[derive(Default)]
+pub struct Schedule {
+    releases: Vec<(u64, u8)>,
+}
+
+impl Schedule {
+    pub fn hold(&mut self, mask: u8, frames: u64) {
+        self.cancel(mask);
+        self.releases.push((frames, mask));
+    }
+
+    pub fn cancel(&mut self, mask: u8) {
+        self.releases.retain(|&(_, held)| held & mask == 0);
+    }
+
+    pub fn queued(&self) -> u8 {
+        self.releases
+            .iter()
+            .fold(0, |queued, &(_, mask)| queued | mask)
+    }
+
+    // the advance has to run at least this long to release everything
+    pub fn last(&self) -> u64 {
+        self.releases.iter().map(|&(at, _)| at).max().unwrap_or(0)
+    }
+
+    pub fn release(&mut self, frame: u64, input: &AtomicU8) {
+        self.releases.retain(|&(at, mask)| {
+            if at <= frame {
+                input.fetch_and(!mask, Ordering::Relaxed);
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: u8 = 1 << 0;
+    const RIGHT: u8 = 1 << 7;
+
+    #[test]
+    fn releases_after_the_hold() {
+        let input = AtomicU8::new(A | RIGHT);
+        let mut schedule = Schedule::default();
+        schedule.hold(RIGHT, 15);
+        assert_eq!(schedule.queued(), RIGHT);
+        assert_eq!(schedule.last(), 15);
+
+        schedule.release(14, &input);
+        assert_eq!(input.load(Ordering::Relaxed), A | RIGHT);
+        schedule.release(15, &input);
+        assert_eq!(input.load(Ordering::Relaxed), A);
+        assert_eq!(schedule.queued(), 0);
+    }
+
+    #[test]
+    fn cancel_keeps_the_button_held() {
+        let input = AtomicU8::new(RIGHT);
+        let mut schedule = Schedule::default();
+        schedule.hold(RIGHT, 5);
+        schedule.cancel(RIGHT);
+        schedule.release(60, &input);
+        assert_eq!(input.load(Ordering::Relaxed), RIGHT);
+    }
+
+    #[test]
+    fn holding_again_restarts_the_timer() {
+        let mut schedule = Schedule::default();
+        schedule.hold(RIGHT, 5);
+        schedule.hold(RIGHT, 30);
+        assert_eq!(schedule.last(), 30);
+    }
+}
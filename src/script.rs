@@ -0,0 +1,109 @@
+// longest script that can be played at once, ten seconds
+pub const MAX_FRAMES: u64 = 600;
+
+fn button(letter: char) -> Option<u8> {
+    match letter.to_ascii_uppercase() {
+        'A' => Some(1 << 0),
+        'B' => Some(1 << 1),
+        'S' => Some(1 << 2),
+        'T' => Some(1 << 3),
+        'U' => Some(1 << 4),
+        'D' => Some(1 << 5),
+        'L' => Some(1 << 6),
+        'R' => Some(1 << 7),
+        _ => None,
+    }
+}
+
+// "R*30 A+R*10 .*5": buttons joined by + or . for none, held for a number of frames
+pub fn parse(script: &str) -> Result<Vec<(u8, u64)>, String> {
+    let mut steps = Vec::new();
+    let mut total: u64 = 0;
+    for token in script.split_whitespace() {
+        let (buttons, frames) = match token.split_once('*') {
+            Some((buttons, frames)) => {
+                let frames: u64 = frames
+                    .parse()
+                    .map_err(|_| format!("{token}: {frames} is not a frame count"))?;
+                (buttons, frames)
+            }
+            None => (token, 1),
+        };
+
+        let byte = if buttons == "." {
+            0
+        } else {
+            let mut byte = 0;
+            for name in buttons.split('+') {
+                let mut letters = name.chars();
+                let (Some(letter), None) = (letters.next(), letters.next()) else {
+                    return Err(format!("{token}: {name} is not a button"));
+                };
+                byte |= button(letter).ok_or(format!("{token}: {name} is not a button"))?;
+            }
+            byte
+        };
+
+        total = total
+            .checked_add(frames)
+            .filter(|&total| total <= MAX_FRAMES)
+            .ok_or(format!("scripts can be at most {MAX_FRAMES} frames long"))?;
+        if frames > 0 {
+            steps.push((byte, frames));
+        }
+    }
+    if steps.is_empty() {
+        return Err("the script has no frames".into());
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_steps() {
+        let steps = parse("R*30 a+r*10 .*5 B").unwrap();
+        assert_eq!(
+            steps,
+            [(1 << 7, 30), (1 << 0 | 1 << 7, 10), (0, 5), (1 << 1, 1)]
+        );
+    }
+
+    #[test]
+    fn skips_empty_steps() {
+        assert_eq!(parse("A*0 B*2").unwrap(), [(1 << 1, 2)]);
+        assert_eq!(parse("A*0").unwrap_err(), "the script has no frames");
+        assert_eq!(parse("   ").unwrap_err(), "the script has no frames");
+    }
+
+    #[test]
+    fn refuses_invalid_tokens() {
+        assert_eq!(parse("A*x").unwrap_err(), "A*x: x is not a frame count");
+        assert_eq!(parse("A*-1").unwrap_err(), "A*-1: -1 is not a frame count");
+        assert_eq!(parse("A*").unwrap_err(), "A*:  is not a frame count");
+        assert_eq!(parse("AB*2").unwrap_err(), "AB*2: AB is not a button");
+        assert_eq!(parse("A++B").unwrap_err(), "A++B:  is not a button");
+    }
+
+    #[test]
+    fn refuses_unknown_letters() {
+        assert_eq!(parse("X*2").unwrap_err(), "X*2: X is not a button");
+        assert_eq!(parse("A+Q").unwrap_err(), "A+Q: Q is not a button");
+    }
+
+    #[test]
+    fn refuses_long_scripts() {
+        let limit = format!("scripts can be at most {MAX_FRAMES} frames long");
+        assert!(parse(&format!("A*{MAX_FRAMES}")).is_ok());
+        assert_eq!(parse(&format!("A*{}", MAX_FRAMES + 1)).unwrap_err(), limit);
+        assert_eq!(parse("A*300 B*300 .").unwrap_err(), limit);
+        // counts that would overflow the total
+        assert_eq!(parse(&format!("A*1 B*{}", u64::MAX)).unwrap_err(), limit);
+        assert_eq!(
+            parse("A*99999999999999999999").unwrap_err(),
+            "A*99999999999999999999: 99999999999999999999 is not a frame count"
+        );
+    }
+}
@@ -0,0 +1,1549 @@
+use crate::activity::Activity;
+use crate::clock::EmuClock;
+use crate::commands::{self, ephemeral, Command};
+use crate::emulator::{EmuHandle, Emulator};
+use crate::error::{retry, Error, Result};
+use crate::render::{StillFormat, LAYERS};
+use crate::replay::InputLog;
+use crate::rom;
+use crate::schedule::{Schedule, HOLDS};
+use crate::script;
+use crate::vote::{describe, echo, Votes};
+use crate::Config;
+use discord::channel::{Channel, ChannelResource};
+use discord::interaction::{
+    AnyInteraction, CommandInteraction, ComponentInteractionResource, CreateReply, CreateUpdate,
+    InteractionResource, Webhook,
+};
+use discord::message::{
+    ActionRow, ActionRowComponent, Attachment, Button, ButtonStyle, CreateAttachment, CreateEmbed,
+    CreateMessage, Message, MessageFlags, MessageResource, PatchMessage, SelectMenu, SelectOption,
+};
+use discord::request::{Bot, Error as RequestError, File, IndexedOr};
+use discord::resource::Snowflake;
+use discord::user::User;
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::io::ErrorKind;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::watch;
+use tokio::time::{self, Instant};
+
+// right + a
+const RUN_JUMP: u8 = 1 << 7 | 1 << 0;
+
+fn mask(custom_id: &str) -> Option<u8> {
+    match custom_id {
+        "a" => Some(1 << 0),
+        "b" => Some(1 << 1),
+        "select" => Some(1 << 2),
+        "start" => Some(1 << 3),
+        "up" => Some(1 << 4),
+        "down" => Some(1 << 5),
+        "left" => Some(1 << 6),
+        "right" => Some(1 << 7),
+        "run" => Some(RUN_JUMP),
+        _ => None,
+    }
+}
+
+// chords are only released once fully held, partial presses complete them
+pub fn press(input: u8, mask: u8) -> u8 {
+    if input & mask == mask {
+        input & !mask
+    } else {
+        input | mask
+    }
+}
+
+fn style(input: u8, momentary: u8, queued: u8, mask: u8) -> ButtonStyle {
+    if input & mask == 0 {
+        ButtonStyle::Primary
+    } else if mask & !(momentary | queued) == 0 {
+        // released by itself during the next advance
+        ButtonStyle::Danger
+    } else if input & mask == mask {
+        ButtonStyle::Success
+    } else {
+        // part of a chord
+        ButtonStyle::Secondary
+    }
+}
+
+// momentary buttons let go after an advance, presses made during it are kept
+pub fn release(input: &AtomicU8, momentary: u8) -> u8 {
+    input.fetch_and(!momentary, Ordering::Relaxed) & !momentary
+}
+
+// everything the controls show besides the frame
+#[derive(Clone, Copy, Default)]
+struct Panel {
+    input: u8,
+    momentary: u8,
+    queued: u8,
+    turbo: u8,
+    hold: Option<u64>,
+    playing: bool,
+    democracy: bool,
+    rewindable: bool,
+    layers: usize,
+    offline: bool,
+    idle: bool,
+    rendering: bool,
+}
+
+fn components(panel: Panel) -> Vec<ActionRow> {
+    let Panel {
+        input,
+        momentary,
+        queued,
+        turbo,
+        hold,
+        playing,
+        democracy,
+        rewindable,
+        layers,
+        offline,
+        idle,
+        rendering,
+    } = panel;
+    let button = |custom_id: &str, label: Option<&str>, mask: Option<u8>| {
+        ActionRowComponent::Button(Button::Action {
+            style: mask.map_or(ButtonStyle::Secondary, |mask| {
+                style(input, momentary, queued, mask)
+            }),
+            custom_id: custom_id.into(),
+            disabled: offline
+                || label.is_none()
+                || custom_id == "rewind" && !rewindable
+                || custom_id == "next" && rendering,
+            label: Some(label.unwrap_or("_").into()),
+        })
+    };
+    let toggle = |custom_id: &str, label: &str, on: bool| {
+        ActionRowComponent::Button(Button::Action {
+            style: if on {
+                ButtonStyle::Success
+            } else {
+                ButtonStyle::Primary
+            },
+            custom_id: custom_id.into(),
+            disabled: offline,
+            label: Some(label.into()),
+        })
+    };
+    let mut rows = vec![
+        ActionRow::new(vec![
+            button(
+                "mode",
+                Some(if democracy { "Democracy" } else { "Anarchy" }),
+                None,
+            ),
+            button("up", Some("⬆"), Some(1 << 4)),
+            button(
+                "hold",
+                Some(&match hold {
+                    Some(frames) => format!("Hold {frames}f"),
+                    None => "Hold ∞".into(),
+                }),
+                None,
+            ),
+            button("play", Some(if playing { "Pause" } else { "Play" }), None),
+            button("run", Some("🏃"), Some(RUN_JUMP)),
+        ]),
+        ActionRow::new(vec![
+            button("left", Some("⬅"), Some(1 << 6)),
+            toggle("turbo_a", "Turbo 🅰️", turbo & 1 << 0 != 0),
+            button("right", Some("➡"), Some(1 << 7)),
+            toggle("turbo_b", "Turbo 🅱️", turbo & 1 << 1 != 0),
+            button("a", Some("🅰️"), Some(1 << 0)),
+        ]),
+        ActionRow::new(vec![
+            button("select", Some("Select"), Some(1 << 2)),
+            button("down", Some("⬇"), Some(1 << 5)),
+            button("start", Some("Start"), Some(1 << 3)),
+            button("b", Some("🅱️"), Some(1 << 1)),
+            button("rewind", Some("⏪"), None),
+        ]),
+        ActionRow::new(vec![
+            button(
+                "next",
+                Some(if rendering { "Rendering…" } else { "Next" }),
+                None,
+            ),
+            button("reset", Some("Reset"), None),
+            button("save", Some("Save"), None),
+            button("load", Some("Load"), None),
+            button("stop", Some("Stop"), None),
+        ]),
+        ActionRow::new(vec![ActionRowComponent::SelectMenu(SelectMenu::String {
+            custom_id: "layers".into(),
+            options: LAYERS
+                .iter()
+                .enumerate()
+                .map(|(index, &(value, label, _))| SelectOption {
+                    label: label.into(),
+                    value: value.into(),
+                    default: index == layers,
+                })
+                .collect(),
+            disabled: offline,
+        })]),
+    ];
+    if idle {
+        // the only thing left to do is wake it up
+        rows[4] = ActionRow::new(vec![ActionRowComponent::Button(Button::Action {
+            style: ButtonStyle::Success,
+            custom_id: "resume".into(),
+            disabled: false,
+            label: Some("Resume".into()),
+        })]);
+    }
+    rows
+}
+
+// offered with the start reply when the bot went offline during the last game
+pub fn autosave_controls() -> Vec<ActionRow> {
+    vec![ActionRow::new(vec![ActionRowComponent::Button(
+        Button::Action {
+            style: ButtonStyle::Success,
+            custom_id: "autosave".into(),
+            disabled: false,
+            label: Some("Resume autosave".into()),
+        },
+    )])]
+}
+
+// answers a deferred advance or script once its frames are on the message
+async fn played_reply(i: &CommandInteraction, played: Result<()>, content: String) -> Result<bool> {
+    let content = match &played {
+        Ok(()) => content,
+        Err(err) => format!("Could not play the frames: {err}"),
+    };
+    i.followup(&Webhook, ephemeral(content)).await?;
+    played.map(|()| false)
+}
+
+fn embeds(watch: Option<String>) -> Vec<CreateEmbed> {
+    watch
+        .into_iter()
+        .map(|watch| CreateEmbed::default().description(watch))
+        .collect()
+}
+
+// the control message of a channel is remembered so restarts edit it instead of posting a new one
+fn message_path(channel: Snowflake<Channel>) -> PathBuf {
+    format!("saves/{channel}.msg").into()
+}
+
+fn last_message(channel: Snowflake<Channel>) -> Option<Snowflake<Message>> {
+    let id = fs::read_to_string(message_path(channel)).ok()?;
+    id.trim().to_string().try_into().ok()
+}
+
+fn remember(channel: Snowflake<Channel>, message: Snowflake<Message>) {
+    let path = message_path(channel);
+    let written = fs::create_dir_all("saves").and_then(|()| fs::write(&path, message.to_string()));
+    if let Err(err) = written {
+        eprintln!("could not write {}: {err}", path.display());
+    }
+}
+
+async fn display(
+    client: &Bot,
+    img: File,
+    watch: Option<String>,
+    channel: Snowflake<Channel>,
+) -> Result<Message> {
+    // reuse the old panel, the fresh frame replaces its attachment
+    if let Some(id) = last_message(channel) {
+        let edit = async {
+            channel
+                .get_message(client, id)
+                .await?
+                .patch(
+                    client,
+                    PatchMessage::default()
+                        .content("")
+                        .embeds(embeds(watch.clone()))
+                        .components(components(Panel::default()))
+                        .attachments(IndexedOr(vec![CreateAttachment::new(img.clone())], vec![])),
+                )
+                .await
+        };
+        match edit.await {
+            Ok(message) => return Ok(message),
+            Err(err) => {
+                eprintln!("could not reuse message {id} in {channel}: {err}, posting a new one")
+            }
+        }
+    }
+
+    let send = || {
+        channel.send_message(
+            client,
+            CreateMessage::default()
+                .embeds(embeds(watch.clone()))
+                .components(components(Panel::default()))
+                .attachments(vec![CreateAttachment::new(img.clone())].into()),
+        )
+    };
+
+    // a failed upload gets one more try, rate limits are waited out
+    let message = match retry(send).await {
+        Ok(message) => message,
+        Err(err) => {
+            eprintln!("could not upload the first frame to {channel}: {err}, retrying");
+            retry(send).await?
+        }
+    };
+    remember(channel, message.id);
+    Ok(message)
+}
+
+// attachment shown on the control message, None once discord stops serving it
+struct AttachmentRef {
+    id: Option<Snowflake<Attachment>>,
+    edits: u32,
+}
+
+impl AttachmentRef {
+    fn new(id: Snowflake<Attachment>) -> Self {
+        Self {
+            id: Some(id),
+            edits: 0,
+        }
+    }
+
+    // older references sometimes get invalidated, so re-upload after enough edits
+    fn reusable(&self, max_edits: u32) -> Option<Snowflake<Attachment>> {
+        self.id.filter(|_| self.edits < max_edits)
+    }
+
+    fn refresh(&mut self, attachments: &[Attachment], uploaded: bool) {
+        self.track(
+            attachments.first().map(|attachment| attachment.id),
+            uploaded,
+        );
+    }
+
+    // an edit that came back without the attachment means the reference died
+    fn track(&mut self, id: Option<Snowflake<Attachment>>, uploaded: bool) {
+        match id {
+            Some(id) => {
+                self.id = Some(id);
+                self.edits = if uploaded { 0 } else { self.edits + 1 };
+            }
+            None => self.id = None,
+        }
+    }
+}
+
+// edits the control message with the frames played since the last edit
+struct AutoRun {
+    last: Instant,
+    interval: Duration,
+}
+
+const AUTO_RUN_INTERVAL: Duration = Duration::from_secs(3);
+const AUTO_RUN_MAX_INTERVAL: Duration = Duration::from_secs(30);
+
+// how long shutdown waits on discord before giving up on the message
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Events = UnboundedReceiver<AnyInteraction>;
+
+pub struct Session {
+    client: Arc<Bot>,
+    config: Arc<Config>,
+    channel: Snowflake<Channel>,
+    owner: Snowflake<User>,
+    players: HashSet<Snowflake<User>>,
+    // players who get their own presses confirmed
+    echoes: HashSet<Snowflake<User>>,
+    // None while idle, the log is parked until someone resumes
+    emu: Option<EmuHandle>,
+    parked: Option<InputLog>,
+    last_active: Instant,
+    input: Arc<AtomicU8>,
+    clock: EmuClock,
+    attachment: AttachmentRef,
+    message: Message,
+    auto: Option<AutoRun>,
+    votes: Option<Votes>,
+    hold: Option<u64>,
+    schedule: Schedule,
+    checkpoints: VecDeque<(u64, u8)>,
+    layers: usize,
+    still: StillFormat,
+    turbo: u8,
+    activity: Activity,
+}
+
+impl Session {
+    pub async fn start(
+        client: Arc<Bot>,
+        config: Arc<Config>,
+        channel: Snowflake<Channel>,
+        owner: Snowflake<User>,
+    ) -> Result<Session> {
+        // create emulator
+        let input = Arc::new(AtomicU8::new(0));
+        let emu = EmuHandle::spawn(config.clone(), input.clone(), InputLog::default());
+        let clock = EmuClock::new(config.region);
+
+        let (img, watch) = emu
+            .run(|emu| (emu.still(), emu.watch()))
+            .await
+            .map_err(|_| Error::Emulator)?;
+        let message = display(&client, img?, watch, channel).await?;
+        let attachment = AttachmentRef::new(message.attachments[0].id);
+        let activity = Activity::new(config.activity_log);
+        let still = config.still;
+
+        Ok(Session {
+            client,
+            config,
+            channel,
+            owner,
+            players: HashSet::new(),
+            echoes: HashSet::new(),
+            emu: Some(emu),
+            parked: None,
+            last_active: Instant::now(),
+            input,
+            clock,
+            attachment,
+            message,
+            auto: None,
+            votes: None,
+            hold: None,
+            schedule: Schedule::default(),
+            checkpoints: VecDeque::new(),
+            layers: 0,
+            still,
+            turbo: 0,
+            activity,
+        })
+    }
+
+    fn is_owner(&self, user: Snowflake<User>) -> bool {
+        user == self.owner || self.config.owner == Some(user)
+    }
+
+    fn can_play(&self, user: Snowflake<User>) -> bool {
+        self.is_owner(user) || self.players.contains(&user)
+    }
+
+    fn not_yours(&self) -> CreateReply {
+        ephemeral(format!(
+            "This game belongs to <@{}>, ask them for an invite",
+            self.owner
+        ))
+    }
+
+    // flips a button in anarchy mode, timing it when a hold length is set
+    fn press(&mut self, mask: u8) -> u8 {
+        let byte = press(self.input.load(Ordering::Relaxed), mask);
+        self.input.store(byte, Ordering::Relaxed);
+        match self.hold {
+            Some(frames) if byte & mask == mask => self.schedule.hold(mask, frames),
+            _ => self.schedule.cancel(mask),
+        }
+        byte
+    }
+
+    fn panel(&self, input: u8) -> Panel {
+        Panel {
+            input,
+            momentary: self.config.profile.momentary(),
+            queued: self.schedule.queued(),
+            turbo: self.turbo,
+            hold: self.hold,
+            playing: self.auto.is_some(),
+            democracy: self.votes.is_some(),
+            rewindable: !self.checkpoints.is_empty(),
+            layers: self.layers,
+            offline: false,
+            idle: false,
+            rendering: false,
+        }
+    }
+
+    fn controls(&self, input: u8) -> Vec<ActionRow> {
+        components(self.panel(input))
+    }
+
+    fn save_path(&self) -> PathBuf {
+        match &self.config.game {
+            Some(game) => format!("saves/{}-{game}.sav", self.channel).into(),
+            None => format!("saves/{}.sav", self.channel).into(),
+        }
+    }
+
+    // edits the control message, waiting out rate limits
+    async fn edit(&self, patch: PatchMessage) -> Result<Message> {
+        Ok(retry(|| self.message.patch(&self.client, patch.clone())).await?)
+    }
+
+    pub fn has_save(&self) -> bool {
+        self.save_path().exists()
+    }
+
+    // written on shutdown, separate so it never overwrites what the players saved
+    fn autosave_path(&self) -> PathBuf {
+        self.save_path().with_extension("auto.sav")
+    }
+
+    pub fn has_autosave(&self) -> bool {
+        self.autosave_path().exists()
+    }
+
+    // replaces the game with a save, returning the buttons held in it
+    async fn restore(
+        &mut self,
+        events: &mut Events,
+        path: PathBuf,
+    ) -> Result<std::result::Result<u8, String>> {
+        let (log, held) = match InputLog::load(&path) {
+            Ok(save) => save,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Ok(Err("There is no save for this channel".into()))
+            }
+            Err(err) => return Ok(Err(format!("Could not load the save: {err}"))),
+        };
+
+        self.emulate(events, move |emu| emu.load(log)).await?;
+        self.input.store(held, Ordering::Relaxed);
+        self.schedule = Schedule::default();
+        self.checkpoints.clear();
+        Ok(Ok(held))
+    }
+
+    // runs a job on the emulator thread while still answering interactions
+    async fn emulate<R: Send + 'static>(
+        &mut self,
+        events: &mut Events,
+        job: impl FnOnce(&mut Emulator) -> R + Send + 'static,
+    ) -> Result<R> {
+        let Some(emu) = &self.emu else {
+            return Err(Error::Emulator);
+        };
+        let mut result = emu.run(job);
+        loop {
+            tokio::select! {
+                result = &mut result => return result.map_err(|_| Error::Emulator),
+                Some(event) = events.recv() => match self.busy(event).await {
+                    // a failed reply to someone else must not abort this job
+                    Ok(()) => {}
+                    Err(Error::Stopped) => return Err(Error::Stopped),
+                    Err(err) => eprintln!("error in the session in {}: {err}", self.channel),
+                },
+            }
+        }
+    }
+
+    // remembers where the advance started so it can be rewound
+    async fn advance(
+        &mut self,
+        events: &mut Events,
+        frames: u64,
+        until_ready: bool,
+        schedule: Schedule,
+    ) -> Result<File> {
+        let held = self.input.load(Ordering::Relaxed);
+        let (start, frames, img) = self
+            .emulate(events, move |emu| {
+                let start = emu.log.frames();
+                let img = emu.advance(frames, until_ready, schedule);
+                (start, emu.log.frames() - start, img)
+            })
+            .await?;
+        self.activity.frames += frames;
+        self.checkpoint(start, held);
+        img
+    }
+
+    async fn script(&mut self, events: &mut Events, steps: Vec<(u8, u64)>) -> Result<File> {
+        let held = self.input.load(Ordering::Relaxed);
+        let frames: u64 = steps.iter().map(|&(_, frames)| frames).sum();
+        let (start, img) = self
+            .emulate(events, move |emu| (emu.log.frames(), emu.script(steps)))
+            .await?;
+        self.activity.frames += frames;
+        self.checkpoint(start, held);
+        img
+    }
+
+    fn checkpoint(&mut self, start: u64, held: u8) {
+        if self.config.rewind_depth > 0 {
+            if self.checkpoints.len() == self.config.rewind_depth {
+                self.checkpoints.pop_front();
+            }
+            self.checkpoints.push_back((start, held));
+        }
+    }
+
+    // shows an animation made outside of a button press
+    async fn show(&mut self, events: &mut Events, img: File) -> Result<()> {
+        let byte = release(&self.input, self.config.profile.momentary());
+        self.repaint(events, img, byte).await
+    }
+
+    // puts a new frame on the message without touching the buttons
+    async fn repaint(&mut self, events: &mut Events, img: File, byte: u8) -> Result<()> {
+        let embeds = self.watch(events).await?;
+        let msg = self
+            .edit(
+                PatchMessage::default()
+                    .embeds(embeds)
+                    .components(self.controls(byte))
+                    .attachments(IndexedOr(vec![CreateAttachment::new(img)], vec![])),
+            )
+            .await?;
+
+        self.attachment.refresh(&msg.attachments, true);
+        self.message = msg;
+        Ok(())
+    }
+
+    async fn busy(&mut self, event: AnyInteraction) -> Result<()> {
+        self.last_active = Instant::now();
+        match event {
+            AnyInteraction::Command(i) => {
+                i.reply(
+                    &Webhook,
+                    ephemeral("The emulator is busy, try again in a moment"),
+                )
+                .await?;
+            }
+            AnyInteraction::Component(i) if !self.can_play(i.user.id) => {
+                i.reply(&Webhook, self.not_yours()).await?;
+            }
+            AnyInteraction::Component(i) => {
+                self.activity.record(i.user.id, i.data.custom_id.as_str());
+
+                // toggles still apply, the running job shows them when it is done
+                let mut echoed = None;
+                match (mask(&i.data.custom_id), &mut self.votes) {
+                    (Some(mask), Some(votes)) => {
+                        // counts towards the next window
+                        votes.cast(i.user.id, mask, self.config.vote_window);
+                    }
+                    (Some(mask), None) => {
+                        let byte = self.press(mask);
+                        echoed = Some(echo(mask, byte));
+                    }
+                    // auto-run spends most of its time in a job, so pausing can't wait for it
+                    (None, _) if i.data.custom_id == "play" => self.toggle_auto(),
+                    (None, _) if i.data.custom_id == "stop" => {
+                        i.update(&Webhook, self.stopped()).await?;
+                        return Err(Error::Stopped);
+                    }
+                    // a second next and everything else would have to wait for the job
+                    _ => {
+                        i.reply(
+                            &Webhook,
+                            ephemeral("The emulator is busy, try again in a moment"),
+                        )
+                        .await?;
+                        return Ok(());
+                    }
+                }
+                i.defer_update(&Webhook).await?;
+                if let Some(content) = echoed.filter(|_| self.echoes.contains(&i.user.id)) {
+                    i.followup(&Webhook, ephemeral(content)).await?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn toggle_auto(&mut self) {
+        self.auto = match self.auto {
+            Some(_) => None,
+            None => Some(AutoRun {
+                last: Instant::now(),
+                interval: AUTO_RUN_INTERVAL,
+            }),
+        };
+    }
+
+    // keeps the last frame but removes the controls
+    fn stopped(&self) -> CreateUpdate {
+        let attachments = match self.attachment.id {
+            Some(id) => IndexedOr(vec![], vec![id.into()]),
+            None => IndexedOr(vec![], vec![]),
+        };
+        CreateUpdate::default()
+            .components(vec![])
+            .attachments(attachments)
+    }
+
+    async fn auto_run(&mut self, events: &mut Events) -> Result<()> {
+        let Some(auto) = &mut self.auto else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let frames = self.clock.frames(now - auto.last);
+        auto.last = now;
+
+        let schedule = mem::take(&mut self.schedule);
+        let img = self.advance(events, frames, false, schedule).await?;
+        // paused while the frames were played, leave a still of where it stopped instead
+        let img = match self.auto {
+            Some(_) => img,
+            None => self.emulate(events, |emu| emu.still()).await??,
+        };
+        let embeds = self.watch(events).await?;
+        let byte = release(&self.input, self.config.profile.momentary());
+
+        // rate limits back off the interval here instead of being waited out
+        let edit = self
+            .message
+            .patch(
+                &self.client,
+                PatchMessage::default()
+                    .embeds(embeds)
+                    .components(self.controls(byte))
+                    .attachments(IndexedOr(vec![CreateAttachment::new(img)], vec![])),
+            )
+            .await;
+
+        match edit {
+            Ok(msg) => {
+                if let Some(auto) = &mut self.auto {
+                    auto.interval = AUTO_RUN_INTERVAL;
+                }
+                self.attachment.refresh(&msg.attachments, true);
+                self.message = msg;
+            }
+            Err(RequestError::RateLimited(_)) => {
+                // back off instead of hammering the api
+                if let Some(auto) = &mut self.auto {
+                    auto.interval = (auto.interval * 2).min(AUTO_RUN_MAX_INTERVAL);
+                }
+            }
+            Err(err) => return Err(err.into()),
+        }
+        Ok(())
+    }
+
+    // plays the winning input and opens a new window
+    async fn close_vote(&mut self, events: &mut Events) -> Result<()> {
+        let Some(votes) = self.votes.replace(Votes::default()) else {
+            return Ok(());
+        };
+        let winner = votes.winner();
+        self.input.store(winner, Ordering::Relaxed);
+
+        let img = self.advance(events, 10, true, Schedule::default()).await?;
+        let embeds = self.watch(events).await?;
+        let byte = release(&self.input, self.config.profile.momentary());
+
+        let msg = self
+            .edit(
+                PatchMessage::default()
+                    .content(format!(
+                        "Votes: {}\nPlayed {}",
+                        votes.summary(),
+                        describe(winner)
+                    ))
+                    .embeds(embeds)
+                    .components(self.controls(byte))
+                    .attachments(IndexedOr(vec![CreateAttachment::new(img)], vec![])),
+            )
+            .await?;
+
+        self.attachment.refresh(&msg.attachments, true);
+        self.message = msg;
+        Ok(())
+    }
+
+    // ram watch of the current frame
+    async fn watch(&mut self, events: &mut Events) -> Result<Vec<CreateEmbed>> {
+        let watch = self.emulate(events, |emu| emu.watch()).await?;
+        Ok(embeds(watch))
+    }
+
+    // update with a fresh still of the current frame
+    async fn redraw(&mut self, events: &mut Events, input: u8) -> Result<CreateUpdate> {
+        let (img, watch) = self
+            .emulate(events, |emu| (emu.still(), emu.watch()))
+            .await?;
+        Ok(CreateUpdate::default()
+            .embeds(embeds(watch))
+            .components(self.controls(input))
+            .attachments(IndexedOr(vec![CreateAttachment::new(img?)], vec![])))
+    }
+
+    // autosaves the game and leaves the controls disabled until someone starts it again
+    // failures are only logged, the bot is going down either way
+    async fn shutdown(&mut self, events: &mut Events) {
+        let path = self.autosave_path();
+        let held = self.input.load(Ordering::Relaxed);
+        let saved = match &self.parked {
+            Some(log) => log.save(&path, held).map_err(|err| err.to_string()),
+            None => match self
+                .emulate(events, move |emu| emu.log.save(&path, held))
+                .await
+            {
+                Ok(saved) => saved.map_err(|err| err.to_string()),
+                Err(err) => Err(err.to_string()),
+            },
+        };
+        let content = match saved {
+            Ok(()) => "The bot went offline, use /nes start and Resume autosave to continue".into(),
+            Err(err) => format!("The bot went offline and could not save the game: {err}"),
+        };
+
+        let panel = Panel {
+            offline: true,
+            ..self.panel(held)
+        };
+        let edit = self.message.patch(
+            &self.client,
+            PatchMessage::default()
+                .content(content)
+                .components(components(panel)),
+        );
+        match time::timeout(SHUTDOWN_TIMEOUT, edit).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => eprintln!("could not disable the controls in {}: {err}", self.channel),
+            Err(_) => eprintln!("could not disable the controls in {} in time", self.channel),
+        }
+    }
+
+    // nobody is playing, free the emulator but keep its inputs to resume from
+    async fn sleep(&mut self, events: &mut Events) -> Result<()> {
+        let log = self.emulate(events, |emu| mem::take(&mut emu.log)).await?;
+        // on disk as well, a paused game survives the bot going down
+        let path = self.autosave_path();
+        if let Err(err) = log.save(&path, self.input.load(Ordering::Relaxed)) {
+            eprintln!("could not write {}: {err}", path.display());
+        }
+        self.parked = Some(log);
+        self.emu = None;
+        self.auto = None;
+        if self.votes.is_some() {
+            self.votes = Some(Votes::default());
+        }
+
+        let panel = Panel {
+            offline: true,
+            idle: true,
+            ..self.panel(self.input.load(Ordering::Relaxed))
+        };
+        let msg = self
+            .edit(
+                PatchMessage::default()
+                    .content("Paused after a while without input")
+                    .components(components(panel)),
+            )
+            .await?;
+        self.attachment.refresh(&msg.attachments, false);
+        self.message = msg;
+        Ok(())
+    }
+
+    // everything but resuming waits until the emulator is back
+    async fn asleep(&mut self, events: &mut Events, event: AnyInteraction) -> Result<bool> {
+        let i = match event {
+            AnyInteraction::Command(i) => {
+                let reply = ephemeral("This game is paused, press Resume to continue");
+                i.reply(&Webhook, reply).await?;
+                return Ok(false);
+            }
+            AnyInteraction::Component(i) if !self.can_play(i.user.id) => {
+                i.reply(&Webhook, self.not_yours()).await?;
+                return Ok(false);
+            }
+            AnyInteraction::Component(i) if i.data.custom_id == "resume" => i,
+            AnyInteraction::Component(i) => {
+                let reply = ephemeral("This game is paused, press Resume to continue");
+                i.reply(&Webhook, reply).await?;
+                return Ok(false);
+            }
+            _ => return Ok(false),
+        };
+
+        // a fresh emulator boots once and replays the parked inputs, the held buttons never changed
+        let log = self.parked.take().unwrap_or_default();
+        let (layers, still, turbo) = (LAYERS[self.layers].2, self.still, self.turbo);
+        self.emu = Some(EmuHandle::spawn(
+            self.config.clone(),
+            self.input.clone(),
+            log,
+        ));
+        self.last_active = Instant::now();
+        let _ = fs::remove_file(self.autosave_path());
+        self.emulate(events, move |emu| {
+            emu.layers = layers;
+            emu.still = still;
+            emu.turbo = turbo;
+        })
+        .await?;
+
+        // display
+        let byte = self.input.load(Ordering::Relaxed);
+        let update = self.redraw(events, byte).await?.content("");
+        let msg = i.update(&Webhook, update).await?.get(&Webhook).await?;
+
+        self.attachment.refresh(&msg.attachments, true);
+        self.message = msg;
+        Ok(false)
+    }
+
+    pub async fn run(mut self, mut events: Events, mut stopped: watch::Receiver<bool>) {
+        loop {
+            let deadline = self.auto.as_ref().map(|auto| auto.last + auto.interval);
+            let vote_deadline = self.votes.as_ref().and_then(|votes| votes.deadline);
+            let result = tokio::select! {
+                event = events.recv() => match event {
+                    Some(event) => self.handle(&mut events, event).await,
+                    None => break,
+                },
+                _ = time::sleep_until(deadline.unwrap_or_else(Instant::now)),
+                    if deadline.is_some() =>
+                {
+                    self.auto_run(&mut events).await.map(|()| false)
+                }
+                _ = time::sleep_until(vote_deadline.unwrap_or_else(Instant::now)),
+                    if vote_deadline.is_some() =>
+                {
+                    self.close_vote(&mut events).await.map(|()| false)
+                }
+                _ = time::sleep_until(self.last_active + self.config.idle_timeout),
+                    if self.emu.is_some() =>
+                {
+                    self.sleep(&mut events).await.map(|()| false)
+                }
+                _ = stopped.changed() => {
+                    // main waits on every session, so this always ends it
+                    self.shutdown(&mut events).await;
+                    Ok(true)
+                }
+            };
+
+            // a failed edit should not end the game, a dead emulator does
+            match result {
+                Ok(false) => {}
+                Ok(true) | Err(Error::Stopped) => break,
+                Err(Error::Emulator) => {
+                    eprintln!(
+                        "the emulator in {} crashed, ending its session",
+                        self.channel
+                    );
+                    break;
+                }
+                Err(err) => eprintln!("error in the session in {}: {err}", self.channel),
+            }
+        }
+    }
+
+    // returns whether the session should end
+    async fn handle(&mut self, events: &mut Events, event: AnyInteraction) -> Result<bool> {
+        if self.emu.is_none() {
+            return self.asleep(events, event).await;
+        }
+        self.last_active = Instant::now();
+
+        let i = match event {
+            AnyInteraction::Command(i) => {
+                self.activity.record(i.user.id, commands::name(&i.data));
+                let reply = match commands::parse(&i.data) {
+                    Some(Command::Start | Command::Load { .. } | Command::Games(Some(_))) => {
+                        ephemeral("A game is already running in this channel")
+                    }
+                    Some(Command::Stats) => CreateReply::default().embeds(vec![
+                        CreateEmbed::default().description(self.activity.summary()),
+                    ]),
+                    Some(Command::Log) => CreateReply::default()
+                        .attachments(vec![CreateAttachment::new(self.activity.csv())].into()),
+                    Some(Command::Echo(true)) => {
+                        self.echoes.insert(i.user.id);
+                        ephemeral("Your presses will be confirmed to you")
+                    }
+                    Some(Command::Echo(false)) => {
+                        self.echoes.remove(&i.user.id);
+                        ephemeral("Your presses will no longer be confirmed")
+                    }
+                    Some(Command::Games(None)) => {
+                        let server = i
+                            .guild_id
+                            .map_or(self.channel.to_string(), |guild| guild.to_string());
+                        ephemeral(rom::games(&server))
+                    }
+                    Some(Command::Export) => {
+                        let movie = self.emulate(events, |emu| emu.movie()).await??;
+                        CreateReply::default()
+                            .attachments(vec![CreateAttachment::new(movie)].into())
+                    }
+                    Some(Command::Advance(_) | Command::Script(_) | Command::Format { .. })
+                        if !self.can_play(i.user.id) =>
+                    {
+                        self.not_yours()
+                    }
+                    Some(Command::Advance(frames)) => {
+                        match u64::try_from(frames)
+                            .ok()
+                            .filter(|frames| (1..=script::MAX_FRAMES).contains(frames))
+                        {
+                            Some(frames) => {
+                                // rendering can take longer than discord waits for a reply
+                                i.defer(&Webhook, MessageFlags::EPHEMERAL).await?;
+                                let played = async {
+                                    // timed holds are released during the advance, as with next
+                                    let schedule = mem::take(&mut self.schedule);
+                                    let img = self.advance(events, frames, false, schedule).await?;
+                                    self.show(events, img).await
+                                }
+                                .await;
+                                return played_reply(
+                                    &i,
+                                    played,
+                                    format!("Advanced {frames} frames"),
+                                )
+                                .await;
+                            }
+                            None => ephemeral(format!(
+                                "Advance between 1 and {} frames",
+                                script::MAX_FRAMES
+                            )),
+                        }
+                    }
+                    Some(Command::Script(inputs)) => match script::parse(&inputs) {
+                        Ok(steps) => {
+                            let frames: u64 = steps.iter().map(|&(_, frames)| frames).sum();
+                            i.defer(&Webhook, MessageFlags::EPHEMERAL).await?;
+                            let played = async {
+                                let img = self.script(events, steps).await?;
+                                self.show(events, img).await
+                            }
+                            .await;
+                            return played_reply(&i, played, format!("Played {frames} frames"))
+                                .await;
+                        }
+                        Err(err) => ephemeral(err),
+                    },
+                    Some(Command::Format { format, quality }) => {
+                        match commands::still(&format, quality) {
+                            Ok(still) => {
+                                self.still = still;
+                                let img = self
+                                    .emulate(events, move |emu| {
+                                        emu.still = still;
+                                        emu.still()
+                                    })
+                                    .await??;
+                                let byte = self.input.load(Ordering::Relaxed);
+                                self.repaint(events, img, byte).await?;
+                                ephemeral(format!("Frames are now posted as {format}"))
+                            }
+                            Err(err) => ephemeral(err),
+                        }
+                    }
+                    Some(_) if !self.is_owner(i.user.id) => {
+                        ephemeral(format!("Only <@{}> can do that", self.owner))
+                    }
+                    Some(Command::Poke { address, value }) => {
+                        match commands::poke(&address, &value) {
+                            Ok((address, value)) => {
+                                let old = self
+                                    .emulate(events, move |emu| emu.poke(address, value))
+                                    .await?;
+                                ephemeral(format!("{address:#06x}: {old:#04x} → {value:#04x}"))
+                            }
+                            Err(err) => ephemeral(err),
+                        }
+                    }
+                    Some(Command::Invite(user)) => {
+                        self.players.insert(user);
+                        ephemeral(format!("<@{user}> can now play"))
+                    }
+                    Some(Command::Uninvite(Some(user))) => {
+                        self.players.remove(&user);
+                        ephemeral(format!("<@{user}> can no longer play"))
+                    }
+                    Some(Command::Uninvite(None)) => {
+                        self.players.clear();
+                        ephemeral("Only you can play now")
+                    }
+                    None => return Ok(false),
+                };
+                i.reply(&Webhook, reply).await?;
+                return Ok(false);
+            }
+            AnyInteraction::Component(i) if !self.can_play(i.user.id) => {
+                i.reply(&Webhook, self.not_yours()).await?;
+                return Ok(false);
+            }
+            AnyInteraction::Component(i) => i,
+            _ => return Ok(false),
+        };
+        self.activity.record(i.user.id, i.data.custom_id.as_str());
+
+        let mut byte = self.input.load(Ordering::Relaxed);
+        match i.data.custom_id.as_str() {
+            "next" => {
+                // answer right away, waiting for the ready loop can take longer than discord allows
+                let attachments = match self.attachment.id {
+                    Some(id) => IndexedOr(vec![], vec![id.into()]),
+                    None => IndexedOr(vec![], vec![]),
+                };
+                let panel = Panel {
+                    rendering: true,
+                    ..self.panel(byte)
+                };
+                let msg = i
+                    .update(
+                        &Webhook,
+                        CreateUpdate::default()
+                            .components(components(panel))
+                            .attachments(attachments),
+                    )
+                    .await?
+                    .get(&Webhook)
+                    .await?;
+                self.attachment.refresh(&msg.attachments, false);
+                self.message = msg;
+
+                let schedule = mem::take(&mut self.schedule);
+                let rendered: Result<Message> = async {
+                    let img = self.advance(events, 10, true, schedule).await?;
+                    let embeds = self.watch(events).await?;
+                    byte = release(&self.input, self.config.profile.momentary());
+
+                    // display, clearing an error left by an earlier press
+                    self.edit(
+                        PatchMessage::default()
+                            .content("")
+                            .embeds(embeds)
+                            .components(self.controls(byte))
+                            .attachments(IndexedOr(vec![CreateAttachment::new(img)], vec![])),
+                    )
+                    .await
+                }
+                .await;
+
+                let msg = match rendered {
+                    Ok(msg) => msg,
+                    Err(Error::Stopped) => return Err(Error::Stopped),
+                    Err(err) => {
+                        // never leave next disabled, whichever step failed
+                        byte = release(&self.input, self.config.profile.momentary());
+                        let _ = self
+                            .edit(
+                                PatchMessage::default()
+                                    .content(format!("Could not render the next frames: {err}"))
+                                    .components(self.controls(byte)),
+                            )
+                            .await;
+                        return Err(err);
+                    }
+                };
+
+                self.attachment.refresh(&msg.attachments, true);
+                self.message = msg;
+            }
+            "reset" => {
+                self.emulate(events, |emu| emu.reset()).await?;
+                self.activity.resets += 1;
+                byte = 0;
+                self.turbo = 0;
+                self.schedule = Schedule::default();
+                self.checkpoints.clear();
+
+                // display
+                let update = self.redraw(events, byte).await?;
+                let msg = i.update(&Webhook, update).await?.get(&Webhook).await?;
+
+                self.attachment.refresh(&msg.attachments, true);
+                self.message = msg;
+            }
+            "save" => {
+                let path = self.save_path();
+                let saved = self
+                    .emulate(events, move |emu| emu.log.save(&path, byte))
+                    .await?;
+                let reply = match saved {
+                    Ok(()) => "Game saved".into(),
+                    Err(err) => format!("Could not save the game: {err}"),
+                };
+                i.reply(&Webhook, ephemeral(reply)).await?;
+            }
+            "load" => {
+                byte = match self.restore(events, self.save_path()).await? {
+                    Ok(held) => held,
+                    Err(err) => {
+                        i.reply(&Webhook, ephemeral(err)).await?;
+                        return Ok(false);
+                    }
+                };
+
+                // display
+                let update = self.redraw(events, byte).await?;
+                let msg = i.update(&Webhook, update).await?.get(&Webhook).await?;
+
+                self.attachment.refresh(&msg.attachments, true);
+                self.message = msg;
+            }
+            "autosave" => {
+                let path = self.autosave_path();
+                byte = match self.restore(events, path.clone()).await? {
+                    Ok(held) => held,
+                    Err(err) => {
+                        i.reply(&Webhook, ephemeral(err)).await?;
+                        return Ok(false);
+                    }
+                };
+                // resumed once, a later shutdown writes a new one
+                let _ = fs::remove_file(path);
+
+                // the button sits on the start reply, not on the game
+                let (img, watch) = self
+                    .emulate(events, |emu| (emu.still(), emu.watch()))
+                    .await?;
+                let img = img?;
+                let msg = self
+                    .edit(
+                        PatchMessage::default()
+                            .content("")
+                            .embeds(embeds(watch))
+                            .components(self.controls(byte))
+                            .attachments(IndexedOr(vec![CreateAttachment::new(img)], vec![])),
+                    )
+                    .await?;
+                self.attachment.refresh(&msg.attachments, true);
+                self.message = msg;
+
+                let update = CreateUpdate::default()
+                    .content("Resumed the autosave")
+                    .components(vec![]);
+                i.update(&Webhook, update).await?;
+            }
+            "play" => {
+                self.toggle_auto();
+
+                // leave a still of where we paused
+                let update = self.redraw(events, byte).await?;
+                let msg = i.update(&Webhook, update).await?.get(&Webhook).await?;
+
+                self.attachment.refresh(&msg.attachments, true);
+                self.message = msg;
+            }
+            "rewind" => {
+                let Some((frames, held)) = self.checkpoints.pop_back() else {
+                    i.reply(&Webhook, ephemeral("There is nothing to rewind"))
+                        .await?;
+                    return Ok(false);
+                };
+
+                self.emulate(events, move |emu| emu.rewind(frames)).await?;
+                byte = held;
+                self.input.store(byte, Ordering::Relaxed);
+                self.schedule = Schedule::default();
+
+                // display
+                let update = self.redraw(events, byte).await?;
+                let msg = i.update(&Webhook, update).await?.get(&Webhook).await?;
+
+                self.attachment.refresh(&msg.attachments, true);
+                self.message = msg;
+            }
+            "layers" => {
+                let Some(layers) = i
+                    .data
+                    .values
+                    .first()
+                    .and_then(|value| LAYERS.iter().position(|&(v, _, _)| v == value.as_str()))
+                else {
+                    return Ok(false);
+                };
+                self.layers = layers;
+                let options = LAYERS[layers].2;
+                self.emulate(events, move |emu| emu.layers = options)
+                    .await?;
+
+                // display
+                let update = self.redraw(events, byte).await?;
+                let msg = i.update(&Webhook, update).await?.get(&Webhook).await?;
+
+                self.attachment.refresh(&msg.attachments, true);
+                self.message = msg;
+            }
+            "turbo_a" | "turbo_b" => {
+                self.turbo ^= if i.data.custom_id == "turbo_a" {
+                    1 << 0
+                } else {
+                    1 << 1
+                };
+                let turbo = self.turbo;
+                self.emulate(events, move |emu| emu.turbo = turbo).await?;
+
+                // display
+                let update = self.redraw(events, byte).await?;
+                let msg = i.update(&Webhook, update).await?.get(&Webhook).await?;
+
+                self.attachment.refresh(&msg.attachments, true);
+                self.message = msg;
+            }
+            "hold" => {
+                let next = HOLDS.iter().position(|&hold| hold == self.hold).unwrap() + 1;
+                self.hold = HOLDS[next % HOLDS.len()];
+
+                // display
+                let update = self.redraw(events, byte).await?;
+                let msg = i.update(&Webhook, update).await?.get(&Webhook).await?;
+
+                self.attachment.refresh(&msg.attachments, true);
+                self.message = msg;
+            }
+            "mode" if !self.is_owner(i.user.id) => {
+                i.reply(
+                    &Webhook,
+                    ephemeral(format!("Only <@{}> can change the mode", self.owner)),
+                )
+                .await?;
+            }
+            "mode" => {
+                let content = match self.votes {
+                    Some(_) => {
+                        self.votes = None;
+                        String::new()
+                    }
+                    None => {
+                        self.votes = Some(Votes::default());
+                        format!(
+                                "Democracy: presses are votes, the winner plays {}s after the first vote",
+                                self.config.vote_window.as_secs()
+                            )
+                    }
+                };
+
+                // display
+                let update = self.redraw(events, byte).await?.content(content);
+                let msg = i.update(&Webhook, update).await?.get(&Webhook).await?;
+
+                self.attachment.refresh(&msg.attachments, true);
+                self.message = msg;
+            }
+            "select" | "start" if self.votes.is_none() => {
+                // one-shot: held for a single frame instead of toggled
+                let mask = mask(&i.data.custom_id).unwrap();
+                self.input.fetch_or(mask, Ordering::Relaxed);
+                let (img, watch) = self.emulate(events, |emu| (emu.tap(), emu.watch())).await?;
+                self.activity.frames += 1;
+                self.input.fetch_and(!mask, Ordering::Relaxed);
+                let img = img?;
+
+                // display the frame it was held for
+                let msg = i
+                    .update(
+                        &Webhook,
+                        CreateUpdate::default()
+                            .embeds(embeds(watch))
+                            .components(self.controls(byte | mask))
+                            .attachments(IndexedOr(vec![CreateAttachment::new(img)], vec![])),
+                    )
+                    .await?
+                    .get(&Webhook)
+                    .await?;
+
+                self.attachment.refresh(&msg.attachments, true);
+                self.message = msg;
+            }
+            "stop" => {
+                i.update(&Webhook, self.stopped()).await?;
+                return Ok(true);
+            }
+            custom_id => {
+                let Some(mask) = mask(custom_id) else {
+                    return Ok(false);
+                };
+
+                let content = match &mut self.votes {
+                    Some(votes) => {
+                        votes.cast(i.user.id, mask, self.config.vote_window);
+                        format!("Votes so far: {}", votes.summary())
+                    }
+                    None => {
+                        // flip input
+                        byte = self.press(mask);
+                        String::new()
+                    }
+                };
+
+                // display
+                let attachments = match self.attachment.reusable(self.config.max_edits) {
+                    Some(id) => IndexedOr(vec![], vec![id.into()]),
+                    None => {
+                        let img = self.emulate(events, |emu| emu.still()).await??;
+                        IndexedOr(vec![CreateAttachment::new(img)], vec![])
+                    }
+                };
+                let msg = i
+                    .update(
+                        &Webhook,
+                        CreateUpdate::default()
+                            .content(content)
+                            .components(self.controls(byte))
+                            .attachments(attachments),
+                    )
+                    .await?
+                    .get(&Webhook)
+                    .await?;
+
+                let uploaded = self.attachment.reusable(self.config.max_edits).is_none();
+                self.attachment.refresh(&msg.attachments, uploaded);
+                self.message = msg;
+
+                // after the shared edit so it never waits on the echo
+                if self.votes.is_none() && self.echoes.contains(&i.user.id) {
+                    i.followup(&Webhook, ephemeral(echo(mask, byte))).await?;
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile;
+
+    fn id(n: u64) -> Snowflake<Attachment> {
+        n.to_string().try_into().unwrap()
+    }
+
+    // a toggle edit against a fake discord that drops references once, returns the upload count
+    fn presses(attachment: &mut AttachmentRef, presses: u32, invalidate_at: u32) -> u32 {
+        let mut uploads = 0;
+        let mut next_id = 2;
+        for press in 0..presses {
+            let returned = match attachment.reusable(MAX_EDITS) {
+                Some(_) if press == invalidate_at => None,
+                Some(id) => Some(id),
+                None => {
+                    uploads += 1;
+                    next_id += 1;
+                    Some(id(next_id))
+                }
+            };
+            let uploaded = attachment.reusable(MAX_EDITS).is_none();
+            attachment.track(returned, uploaded);
+        }
+        uploads
+    }
+
+    const MAX_EDITS: u32 = 20;
+
+    const A: u8 = 1 << 0;
+    const RIGHT: u8 = 1 << 7;
+    // what smb releases after an advance
+    const MOMENTARY: u8 = 0b0000_1111;
+
+    #[test]
+    fn press_toggles_single_buttons() {
+        assert_eq!(press(0, RIGHT), RIGHT);
+        assert_eq!(press(RIGHT, RIGHT), 0);
+        assert_eq!(press(A, RIGHT), A | RIGHT);
+    }
+
+    #[test]
+    fn press_completes_partial_chords() {
+        assert_eq!(press(0, RUN_JUMP), RUN_JUMP);
+        assert_eq!(press(RIGHT, RUN_JUMP), RUN_JUMP);
+        assert_eq!(press(A, RUN_JUMP), RUN_JUMP);
+        // only a fully held chord is released
+        assert_eq!(press(RUN_JUMP, RUN_JUMP), 0);
+    }
+
+    // next, democracy, auto-run and scripts all end with this
+    #[test]
+    fn release_keeps_sticky_buttons() {
+        let input = AtomicU8::new(A | 1 << 1 | 1 << 2 | 1 << 3 | RIGHT | 1 << 4);
+        assert_eq!(release(&input, MOMENTARY), RIGHT | 1 << 4);
+        assert_eq!(input.load(Ordering::Relaxed), RIGHT | 1 << 4);
+    }
+
+    #[test]
+    fn momentary_buttons_follow_the_profile() {
+        assert_eq!(profile::by_name("smb").unwrap().momentary(), MOMENTARY);
+        // a generic game keeps a and b held, only its menu buttons let go
+        let generic = profile::by_name("generic").unwrap().momentary();
+        let input = AtomicU8::new(A | 1 << 3 | RIGHT);
+        assert_eq!(release(&input, generic), A | RIGHT);
+        assert!(matches!(style(A, generic, 0, A), ButtonStyle::Success));
+    }
+
+    #[test]
+    fn release_after_chord() {
+        let input = AtomicU8::new(RUN_JUMP);
+        assert_eq!(release(&input, MOMENTARY), RIGHT);
+        // the next press of the chord completes it again
+        assert_eq!(press(RIGHT, RUN_JUMP), RUN_JUMP);
+    }
+
+    #[test]
+    fn styles() {
+        assert!(matches!(
+            style(0, MOMENTARY, 0, RIGHT),
+            ButtonStyle::Primary
+        ));
+        assert!(matches!(
+            style(RIGHT, MOMENTARY, 0, RIGHT),
+            ButtonStyle::Success
+        ));
+        assert!(matches!(style(A, MOMENTARY, 0, A), ButtonStyle::Danger));
+        // a held direction with a hold length also lets go by itself
+        assert!(matches!(
+            style(RIGHT, MOMENTARY, RIGHT, RIGHT),
+            ButtonStyle::Danger
+        ));
+    }
+
+    #[test]
+    fn chord_styles() {
+        assert!(matches!(
+            style(0, MOMENTARY, 0, RUN_JUMP),
+            ButtonStyle::Primary
+        ));
+        assert!(matches!(
+            style(RIGHT, MOMENTARY, 0, RUN_JUMP),
+            ButtonStyle::Secondary
+        ));
+        assert!(matches!(
+            style(A, MOMENTARY, 0, RUN_JUMP),
+            ButtonStyle::Secondary
+        ));
+        assert!(matches!(
+            style(RUN_JUMP, MOMENTARY, 0, RUN_JUMP),
+            ButtonStyle::Success
+        ));
+    }
+
+    #[test]
+    fn reuses_until_max_edits() {
+        let mut attachment = AttachmentRef::new(id(1));
+        assert_eq!(presses(&mut attachment, MAX_EDITS, u32::MAX), 0);
+        assert_eq!(presses(&mut attachment, 1, u32::MAX), 1);
+        assert_eq!(attachment.reusable(MAX_EDITS), Some(id(3)));
+    }
+
+    #[test]
+    fn reuploads_once_after_invalidation() {
+        let mut attachment = AttachmentRef::new(id(1));
+        assert_eq!(presses(&mut attachment, 5, 3), 1);
+        // the fresh upload is reused again afterwards
+        assert_eq!(attachment.reusable(MAX_EDITS), Some(id(3)));
+        assert_eq!(attachment.edits, 0);
+    }
+}
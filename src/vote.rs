@@ -0,0 +1,156 @@
+use crate::session::press;
+use discord::resource::Snowflake;
+use discord::user::User;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+const NAMES: [&str; 8] = ["A", "B", "Select", "Start", "Up", "Down", "Left", "Right"];
+
+pub fn describe(input: u8) -> String {
+    let names: Vec<_> = NAMES
+        .iter()
+        .enumerate()
+        .filter(|&(bit, _)| input & 1 << bit != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    if names.is_empty() {
+        "nothing".into()
+    } else {
+        names.join(" + ")
+    }
+}
+
+// confirms a toggle to the player who pressed it
+pub fn echo(mask: u8, input: u8) -> String {
+    let state = if input & mask == mask {
+        "held"
+    } else {
+        "released"
+    };
+    format!(
+        "{} is now {state}, holding {}",
+        describe(mask),
+        describe(input)
+    )
+}
+
+// one input per voter, the window opens with the first vote
+#[derive(Default)]
+pub struct Votes {
+    pub deadline: Option<Instant>,
+    ballots: HashMap<Snowflake<User>, u8>,
+}
+
+impl Votes {
+    pub fn cast(&mut self, user: Snowflake<User>, mask: u8, window: Duration) {
+        self.deadline.get_or_insert_with(|| Instant::now() + window);
+        let ballot = self.ballots.entry(user).or_default();
+        *ballot = press(*ballot, mask);
+    }
+
+    // voters per distinct input, most popular first
+    fn tally(&self) -> Vec<(u8, usize)> {
+        let mut counts: HashMap<u8, usize> = HashMap::new();
+        for &ballot in self.ballots.values() {
+            *counts.entry(ballot).or_default() += 1;
+        }
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        counts
+    }
+
+    pub fn summary(&self) -> String {
+        self.tally()
+            .iter()
+            .map(|&(input, count)| format!("{} ×{count}", describe(input)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    // ties fall back to no input
+    pub fn winner(&self) -> u8 {
+        match self.tally()[..] {
+            [(_, first), (_, second), ..] if first == second => 0,
+            [(input, _), ..] => input,
+            [] => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(10);
+
+    // one ballot per voter
+    fn votes(ballots: &[u8]) -> Votes {
+        let mut votes = Votes::default();
+        for (n, &mask) in (1u64..).zip(ballots) {
+            votes.cast(n.to_string().try_into().unwrap(), mask, WINDOW);
+        }
+        votes
+    }
+
+    #[test]
+    fn majority_wins() {
+        let votes = votes(&[1, 1 << 7, 1, 1 << 7 | 1, 1]);
+        assert_eq!(votes.winner(), 1);
+        assert_eq!(votes.summary(), "A ×3, Right ×1, A + Right ×1");
+    }
+
+    #[test]
+    fn ties_press_nothing() {
+        let votes = votes(&[1, 1 << 7, 1 << 7, 1]);
+        assert_eq!(votes.winner(), 0);
+        assert_eq!(votes.summary(), "A ×2, Right ×2");
+    }
+
+    #[test]
+    fn no_votes_press_nothing() {
+        let votes = Votes::default();
+        assert_eq!(votes.winner(), 0);
+        assert_eq!(votes.summary(), "");
+        assert!(votes.deadline.is_none());
+    }
+
+    #[test]
+    fn voters_change_their_ballot() {
+        let mut votes = votes(&[1 << 7, 1 << 7]);
+        let voter: Snowflake<User> = "3".to_string().try_into().unwrap();
+        votes.cast(voter, 1, WINDOW);
+        votes.cast(voter, 1 << 7, WINDOW);
+        // a second press of A releases it again
+        votes.cast(voter, 1, WINDOW);
+        assert_eq!(votes.summary(), "Right ×3");
+        assert_eq!(votes.winner(), 1 << 7);
+    }
+
+    #[test]
+    fn echoes_held_buttons() {
+        assert_eq!(
+            echo(1 << 7, 1 << 7 | 1),
+            "Right is now held, holding A + Right"
+        );
+    }
+
+    #[test]
+    fn echoes_released_buttons() {
+        assert_eq!(echo(1 << 7, 0), "Right is now released, holding nothing");
+    }
+
+    #[test]
+    fn echoes_chords() {
+        let chord = 1 << 7 | 1;
+        assert_eq!(
+            echo(chord, chord),
+            "A + Right is now held, holding A + Right"
+        );
+        // a partly held chord is not held
+        assert_eq!(
+            echo(chord, 1 << 7),
+            "A + Right is now released, holding Right"
+        );
+    }
+}